@@ -1,154 +1,206 @@
-use std::io::{self, BufRead};
-
-struct NounVerb {
-    stop: usize,
-    curr: (u32, u32),
-    next: (u32, u32),
-}
-
-impl NounVerb {
-    pub fn new(stop: usize) -> Self {
-        Self {
-            stop: stop,
-            curr: (0, 0),
-            next: (0, 0),
-        }
-    }
-}
-
-impl Iterator for NounVerb {
-    type Item = (u32, u32);
-
-    fn next(&mut self) -> Option<Self::Item> {
-        self.curr = self.next;
-
-        if self.next.0 > self.stop as u32 {
-            return None;
-        }
-
-        if self.next.1 > 0 && self.next.1 == self.stop as u32 {
-            self.next.0 += 1;
-            self.next.1 = 0;
-        } else {
-            self.next.1 += 1;
-        }
-
-        Some(self.curr)
-    }
-}
-
+use std::collections::VecDeque;
+use std::env;
+use std::io::{self, BufRead, BufWriter};
+
+use intcode::search::Search;
+use intcode::symbolic::{self, SymbolicError};
+use intcode::trace::{JsonLinesTracer, Tracer};
+use intcode::{parse_program_into_instructions, IntcodeError, Program};
+
+/// Output the gravity assist program must leave at address 0.
+const TARGET: i64 = 19690720;
+
+/// `dec02 [TARGET]` prints the result of the 1202 program alarm and every
+/// noun and verb producing TARGET, 19690720 by default. `dec02 solve [TARGET]`
+/// derives the formula for address 0 instead and solves it for TARGET.
+/// `dec02 trace NOUN VERB` prints a JSON Lines execution trace of a single
+/// combination, for comparing against other interpreters.
 fn main() {
     let stdin = io::stdin();
 
-    let mut source_opcodes: Vec<u32> = vec![];
+    let mut source_opcodes: Vec<i64> = vec![];
     for line in stdin.lock().lines() {
-        source_opcodes.extend(parse_opcode_into_vector(&line.unwrap()));
+        source_opcodes.extend(parse_program_into_instructions(&line.unwrap()).unwrap());
     }
 
-    //for (noun, verb) in NounVerb::new(99) {
-    //    let mut opcodes = source_opcodes.clone();
-    //    opcodes[1] = noun;
-    //    opcodes[2] = verb;
-
-    //    let result = process(&mut opcodes)[0];
-    //    if result == 19690720_u32 {
-    //        eprintln!("Noun: {} with Verb: {} produces correct result: 100 * noun * verb is: {}", noun, verb, (100 * noun + verb));
-    //        break;
-    //    } else {
-    //        eprintln!("Combo {}, {} produced result: {}", noun, verb, result);
-    //    }
-    //}
+    let arguments: Vec<String> = env::args().skip(1).collect();
+    match &arguments[..] {
+        [command, noun, verb] if command == "trace" => {
+            let mut opcodes = source_opcodes;
+            opcodes[1] = noun.parse::<i64>().unwrap();
+            opcodes[2] = verb.parse::<i64>().unwrap();
+
+            let mut tracer = JsonLinesTracer::new(BufWriter::new(io::stdout()));
+            process_traced(opcodes, &mut tracer).unwrap();
+        },
+        [command, target] if command == "solve" => report_solution(&source_opcodes, target.parse::<i64>().unwrap()),
+        [command] if command == "solve" => report_solution(&source_opcodes, TARGET),
+        [target] => search(source_opcodes, target.parse::<i64>().unwrap()),
+        [] => search(source_opcodes, TARGET),
+        _ => {
+            eprintln!("usage: dec02 [TARGET] | dec02 solve [TARGET] | dec02 trace NOUN VERB");
+            std::process::exit(1);
+        },
+    }
 }
 
-enum OptcodeInstruction {
-    Addition,
-    Multiplication,
-    Exit,
-}
+fn search(source_opcodes: Vec<i64>, target: i64) {
+    let mut alarm = source_opcodes.clone();
+    alarm[1] = 12;
+    alarm[2] = 2;
+    eprintln!("1202 program alarm leaves {} at address 0", process(alarm).unwrap()[0]);
+
+    let program = Program::from(source_opcodes);
+    let matches = Search::new(&program)
+        .patch(1, 0..=99)
+        .patch(2, 0..=99)
+        .run(|memory| memory[0] == target)
+        .unwrap();
+
+    if matches.is_empty() {
+        eprintln!("No noun and verb produce {}", target);
+    }
 
-impl OptcodeInstruction {
-    fn decode(input: &u32) -> Self {
-        match input {
-            1 => Self::Addition,
-            2 => Self::Multiplication,
-            99 => Self::Exit,
-            _ => panic!("Unknown opcode"),
-        }
+    for found in matches {
+        let (noun, verb) = (found.values[0], found.values[1]);
+        eprintln!("Noun: {} with Verb: {} produces {}: 100 * noun + verb is: {}", noun, verb, target, 100 * noun + verb);
     }
 }
 
-fn parse_opcode_into_vector(raw: &str) -> Vec<u32> {
-  raw.split(',').map(|s| s.parse::<u32>().unwrap()).collect()
-}
+/// Treat noun and verb as variables, derive the polynomial left at address 0
+/// and solve it for `target`.
+fn solve(source_opcodes: &[i64], target: i64) -> Result<(String, Vec<Vec<i64>>), SymbolicError> {
+    let polynomial = symbolic::execute(source_opcodes, &[1, 2])?.get(0)?;
+    let solutions = symbolic::solve(&polynomial, target, &[0..=99, 0..=99])?;
 
-enum ParameterMode {
-    Position,
-    Immediate,
+    Ok((polynomial.format(&["noun", "verb"])?, solutions))
 }
 
-fn get_parameter_positions(
-    a: usize,
-    b: usize,
-    c: usize,
-    items: &Vec<u32>
-) -> ((usize, usize), usize) {
-    ((items[a] as usize, items[b] as usize), items[c] as usize)
+fn report_solution(source_opcodes: &[i64], target: i64) {
+    let (formula, solutions) = match solve(source_opcodes, target) {
+        Ok(result) => result,
+        Err(error) => {
+            eprintln!("Unable to solve the program symbolically: {}", error);
+            std::process::exit(1);
+        },
+    };
+
+    eprintln!("Address 0 holds: {}", formula);
+    if solutions.is_empty() {
+        eprintln!("No noun and verb produce {}", target);
+    }
+
+    for solution in solutions {
+        let (noun, verb) = (solution[0], solution[1]);
+        eprintln!("{} = {} for noun: {} and verb: {}: 100 * noun + verb is: {}", formula, target, noun, verb, 100 * noun + verb);
+    }
 }
 
-fn process(opcodes: &mut Vec<u32>) -> Vec<u32> {
-    let mut index: usize = 0;
-    let total_indexes = opcodes.len() - 1;
+fn process(opcodes: Vec<i64>) -> Result<Vec<i64>, IntcodeError> {
+    let mut program = Program::from(opcodes);
+    program.run_with(&mut VecDeque::new(), &mut vec![])?;
 
-    loop {
-        let instruction_pointer = OptcodeInstruction::decode(&opcodes[index]);
-        match instruction_pointer {
-            OptcodeInstruction::Exit => break,
-            _ => {},
-        }
+    Ok(program.memory().to_vec())
+}
 
-        let (parameters, address) = get_parameter_positions(
-            index + 1,
-            index + 2,
-            index + 3,
-            &opcodes,
-        );
-
-        match instruction_pointer {
-            OptcodeInstruction::Addition => {
-                opcodes[address] = opcodes[parameters.0] + opcodes[parameters.1];
-                index += 4;
-            },
-            OptcodeInstruction::Multiplication => {
-                opcodes[address] = opcodes[parameters.0] * opcodes[parameters.1];
-                index += 4;
-            },
-            OptcodeInstruction::Exit => {
-                index += 1;
-            },
-        };
-
-        if index > total_indexes {
-            break;
-        }
-    }
+fn process_traced(opcodes: Vec<i64>, tracer: &mut dyn Tracer) -> Result<Vec<i64>, IntcodeError> {
+    let mut program = Program::from(opcodes);
+    program.run_traced(&mut VecDeque::new(), &mut vec![], tracer)?;
 
-    opcodes.to_vec()
+    Ok(program.memory().to_vec())
 }
 
-
 #[cfg(test)]
 mod test {
     use super::*;
+    use proptest::prelude::*;
+
+    /// The original dec02 interpreter: position mode additions and
+    /// multiplications only.
+    fn reference(mut opcodes: Vec<i64>) -> Option<Vec<i64>> {
+        let mut index = 0;
+
+        loop {
+            let (a, b, c) = match opcodes.get(index..index + 4) {
+                Some(&[_, a, b, c]) => (a as usize, b as usize, c as usize),
+                _ => (0, 0, 0),
+            };
+
+            match opcodes[index] {
+                1 => opcodes[c] = opcodes[a].checked_add(opcodes[b])?,
+                2 => opcodes[c] = opcodes[a].checked_mul(opcodes[b])?,
+                99 => return Some(opcodes),
+                _ => return None,
+            }
+
+            index += 4;
+        }
+    }
+
+    /// Additions and multiplications reading from anywhere in the program and
+    /// writing to the data cells after the final `99`.
+    fn gravity_assist_program() -> impl Strategy<Value = Vec<i64>> {
+        (1usize..12, 1usize..8).prop_flat_map(|(instructions, data)| {
+            let code = instructions * 4 + 1;
+            let len = code + data;
+            let instruction = (prop::sample::select(vec![1i64, 2]), 0..len, 0..len, code..len)
+                .prop_map(|(opcode, a, b, c)| vec![opcode, a as i64, b as i64, c as i64]);
+
+            (prop::collection::vec(instruction, instructions), prop::collection::vec(0i64..100, data))
+                .prop_map(|(instructions, data)| [instructions.concat(), vec![99], data].concat())
+        })
+    }
+
+    proptest! {
+        #[test]
+        fn it_agrees_with_the_reference_interpreter(opcodes in gravity_assist_program()) {
+            prop_assert_eq!(process(opcodes.clone()).ok(), reference(opcodes));
+        }
+    }
 
     #[test]
     fn it_can_parse_opcode_into_vector_of_codes() {
         let line: &str = "1,9,10,3,2,3,11,0,99,30,40,50";
-        assert_eq!(parse_opcode_into_vector(&line).len(), 12);
+        assert_eq!(parse_program_into_instructions(line).unwrap().len(), 12);
     }
 
     #[test]
     fn it_decodes_optcodes_into_instructions() {
         let raw_line: &str = "1002,4,3,4,33";
-        let opcodes = parse_opcode_into_vector(&raw_line);
+        let opcodes = parse_program_into_instructions(raw_line).unwrap();
+
+        assert_eq!(process(opcodes), Ok(vec![1002, 4, 3, 4, 99]));
+    }
+
+    #[test]
+    fn it_processes_addition_and_multiplication() {
+        let opcodes = parse_program_into_instructions("1,9,10,3,2,3,11,0,99,30,40,50").unwrap();
+
+        assert_eq!(process(opcodes).unwrap()[0], 3500);
+    }
+
+    #[test]
+    fn it_solves_for_noun_and_verb_symbolically() {
+        let opcodes = parse_program_into_instructions("1,0,0,3,1,1,2,3,1,3,4,3,2,1,24,21,1,21,2,0,99,0,0,0,7").unwrap();
+        let (formula, solutions) = solve(&opcodes, 100).unwrap();
+
+        assert_eq!(formula, "7*noun + verb");
+        assert_eq!(solutions.len(), 14);
+        assert!(solutions.iter().all(|solution| {
+            let mut patched = opcodes.clone();
+            patched[1] = solution[0];
+            patched[2] = solution[1];
+
+            process(patched).unwrap()[0] == 100
+        }));
+    }
+
+    #[test]
+    fn it_traces_every_executed_instruction() {
+        let opcodes = parse_program_into_instructions("1,9,10,3,2,3,11,0,99,30,40,50").unwrap();
+        let mut trace = vec![];
+
+        assert_eq!(process_traced(opcodes.clone(), &mut trace), process(opcodes));
+        assert_eq!(trace.iter().map(|execution| execution.address).collect::<Vec<usize>>(), vec![0, 4, 8]);
     }
 }
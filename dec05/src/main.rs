@@ -1,368 +1,518 @@
-use std::io::{self, BufRead};
+use std::collections::VecDeque;
+use std::env;
+use std::error::Error;
+use std::fs::{self, File};
+use std::io::{self, BufRead, BufReader, BufWriter, Read, Write};
+use std::sync::Arc;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+use intcode::amplifier::{self, Mode};
+use intcode::ascii::Console;
+use intcode::cfg::Cfg;
+use intcode::limits::Limits;
+use intcode::loops::LoopDetection;
+use intcode::network::{self, Nat, Network};
+use intcode::profile::Profiler;
+use intcode::{asm, disasm};
+use intcode::debugger::Debugger;
+use intcode::device::{ConsolePort, CycleCounter, Framebuffer, RandomSource};
+use intcode::io::{LineInput, StderrOutput};
+use intcode::snapshot::Snapshot;
+use intcode::trace::JsonLinesTracer;
+use intcode::{parse_program_into_instructions, Program, Status};
+
+const USAGE: &str = "\
+usage: dec05 [OPTIONS] [SYSTEM_ID]
+       dec05 COMMAND [ARGUMENTS]
+
+Runs the diagnostic program read from stdin with SYSTEM_ID, 5 by default,
+the thermal radiator controller.
+
+Options:
+    --max-steps N                    fail after N instructions
+    --max-memory CELLS               fail when memory grows beyond CELLS cells
+    --max-outputs N                  fail after N outputs
+    --timeout SECONDS                fail after running for SECONDS
+    --detect-loops exact|heuristic   fail on programs that loop forever
+
+Commands, reading the program from stdin unless they name a program FILE:
+    disasm                           print a listing of the program
+    asm                              assemble source text into a program
+    debug FILE                       debug the program in FILE interactively
+    trace [SYSTEM_ID]                print a JSON Lines execution trace
+    amplify [serial|feedback]        search amplifier phase settings
+    network [NODES]                  simulate a packet network, 50 nodes by default
+    checkpoint FILE STEPS [SYSTEM_ID]
+                                     save a snapshot after at most STEPS instructions
+    resume FILE                      continue from a snapshot, reading input from stdin
+    cfg [dot|json]                   print the control flow graph
+    profile FILE [SYSTEM_ID]         print hot spots and save folded stacks to FILE
+    ascii FILE [SCRIPT]              play the ASCII program in FILE, or feed it SCRIPT
+    devices FILE [SEED]              run the program in FILE with a cycle counter at
+                                     1000, a random source at 1001, a console port
+                                     at 1002 and a 40x6 framebuffer from 1100
+";
+
+/// Ticks after which a network simulation gives up.
+const MAX_NETWORK_TICKS: u64 = 1_000_000;
 
-struct Program {
-    data: Vec<i32>,
-    index: usize,
+#[derive(Debug, PartialEq)]
+enum Command {
+    /// Run the diagnostic with the given system ID, within resource limits.
+    Run { system_id: i64, limits: Limits, loops: Option<LoopDetection> },
+    /// Print a listing of the program instead of running it.
+    Disassemble,
+    /// Assemble source text into a comma separated program.
+    Assemble,
+    /// Debug the program stored in the given file interactively.
+    Debug(String),
+    /// Run the diagnostic, writing an execution trace to stdout.
+    Trace(i64),
+    /// Search the phase settings giving the strongest amplifier signal.
+    Amplify(Mode),
+    /// Simulate a network of the given number of nodes behind a NAT.
+    Network(usize),
+    /// Run the diagnostic for a number of instructions and save a snapshot.
+    Checkpoint { path: String, steps: u64, system_id: i64 },
+    /// Continue running the program saved in a snapshot.
+    Resume(String),
+    /// Print the control flow graph of the program.
+    Graph(GraphFormat),
+    /// Run the program stored in a file on an ASCII console, reading lines
+    /// from a script instead of stdin when one is given.
+    Ascii { path: String, script: Option<String> },
+    /// Run the program stored in a file with the demo devices mapped.
+    Devices { path: String, seed: Option<u64> },
+    /// Run the diagnostic under the profiler, saving folded stacks.
+    Profile { path: String, system_id: i64 },
 }
 
-impl Program {
-    fn value(&self, index: usize) -> i32 {
-        self.data[index]
-    }
+#[derive(Debug, PartialEq)]
+enum GraphFormat {
+    Dot,
+    Json,
+}
 
-    fn write(&mut self, index: usize, input: i32) {
-        self.data[index] = input;
+/// Parse the command line, see `USAGE`.
+fn parse_arguments(mut arguments: impl Iterator<Item = String>) -> Result<Command, Box<dyn Error>> {
+    let command = arguments.next();
+
+    match command.as_deref() {
+        Some("disasm") => Ok(Command::Disassemble),
+        Some("asm") => Ok(Command::Assemble),
+        Some("debug") => match arguments.next() {
+            Some(path) => Ok(Command::Debug(path)),
+            None => Err("usage: dec05 debug FILE".into()),
+        },
+        Some("trace") => match arguments.next() {
+            Some(system_id) => Ok(Command::Trace(system_id.parse::<i64>()?)),
+            None => Ok(Command::Trace(5)),
+        },
+        Some("amplify") => match arguments.next().as_deref() {
+            Some("serial") | None => Ok(Command::Amplify(Mode::Serial)),
+            Some("feedback") => Ok(Command::Amplify(Mode::Feedback)),
+            Some(mode) => Err(format!("unknown amplifier mode {:?}", mode).into()),
+        },
+        Some("network") => match arguments.next() {
+            Some(nodes) => Ok(Command::Network(nodes.parse::<usize>()?)),
+            None => Ok(Command::Network(50)),
+        },
+        Some("checkpoint") => match (arguments.next(), arguments.next()) {
+            (Some(path), Some(steps)) => Ok(Command::Checkpoint {
+                path,
+                steps: steps.parse::<u64>()?,
+                system_id: match arguments.next() {
+                    Some(system_id) => system_id.parse::<i64>()?,
+                    None => 5,
+                },
+            }),
+            _ => Err("usage: dec05 checkpoint FILE STEPS [SYSTEM_ID]".into()),
+        },
+        Some("cfg") => match arguments.next().as_deref() {
+            Some("dot") | None => Ok(Command::Graph(GraphFormat::Dot)),
+            Some("json") => Ok(Command::Graph(GraphFormat::Json)),
+            Some(format) => Err(format!("unknown graph format {:?}", format).into()),
+        },
+        Some("ascii") => match arguments.next() {
+            Some(path) => Ok(Command::Ascii { path, script: arguments.next() }),
+            None => Err("usage: dec05 ascii FILE [SCRIPT]".into()),
+        },
+        Some("devices") => match arguments.next() {
+            Some(path) => Ok(Command::Devices {
+                path,
+                seed: arguments.next().map(|seed| seed.parse::<u64>()).transpose()?,
+            }),
+            None => Err("usage: dec05 devices FILE [SEED]".into()),
+        },
+        Some("profile") => match arguments.next() {
+            Some(path) => Ok(Command::Profile {
+                path,
+                system_id: match arguments.next() {
+                    Some(system_id) => system_id.parse::<i64>()?,
+                    None => 5,
+                },
+            }),
+            None => Err("usage: dec05 profile FILE [SYSTEM_ID]".into()),
+        },
+        Some("resume") => match arguments.next() {
+            Some(path) => Ok(Command::Resume(path)),
+            None => Err("usage: dec05 resume FILE".into()),
+        },
+        _ => parse_run(command.into_iter().chain(arguments)),
     }
+}
 
-    fn get_parameter(&mut self, parameter: &Parameter, index: usize) -> i32 {
-        match parameter.mode {
-            ParameterMode::Position => self.value(self.value(index) as usize),
-            ParameterMode::Immediate => self.value(index),
+fn parse_run(mut arguments: impl Iterator<Item = String>) -> Result<Command, Box<dyn Error>> {
+    let mut system_id = None;
+    let mut limits = Limits::new();
+    let mut loops = None;
+
+    while let Some(argument) = arguments.next() {
+        let mut value = || arguments.next().ok_or_else(|| format!("{} needs a value", argument));
+
+        match argument.as_str() {
+            "--max-steps" => limits = limits.steps(value()?.parse::<u64>()?),
+            "--max-memory" => limits = limits.memory(value()?.parse::<usize>()?),
+            "--max-outputs" => limits = limits.outputs(value()?.parse::<u64>()?),
+            "--timeout" => limits = limits.timeout(Duration::try_from_secs_f64(value()?.parse::<f64>()?)?),
+            "--detect-loops" => loops = Some(match value()?.as_str() {
+                "exact" => LoopDetection::Exact,
+                "heuristic" => LoopDetection::Heuristic,
+                mode => return Err(format!("unknown loop detection mode {:?}", mode).into()),
+            }),
+            _ if system_id.is_none() => system_id = Some(argument.parse::<i64>()?),
+            _ => return Err(format!("unexpected argument {:?}", argument).into()),
         }
     }
 
-    fn addition(&mut self, parameters: Vec<Parameter>) {
-        let first_part = self.get_parameter(&parameters[0], self.index + 0);
-        let second_part = self.get_parameter(&parameters[1], self.index + 1);
-        let destination = self.value(self.index + 2);
+    Ok(Command::Run { system_id: system_id.unwrap_or(5), limits, loops })
+}
 
-        self.write(destination as usize, first_part + second_part);
+fn read_program(reader: impl BufRead) -> Result<Vec<i64>, Box<dyn Error>> {
+    let mut data: Vec<i64> = vec![];
+    for line in reader.lines() {
+        data.extend(parse_program_into_instructions(&line?)?);
     }
 
-    fn multiply(&mut self, parameters: Vec<Parameter>) {
-        let first_part = self.get_parameter(&parameters[0], self.index + 0);
-        let second_part = self.get_parameter(&parameters[1], self.index + 1);
-        let destination = self.value(self.index + 2);
+    Ok(data)
+}
 
-        self.write(destination as usize, first_part * second_part);
+fn run(system_id: i64, limits: Limits, loops: Option<LoopDetection>) -> Result<(), Box<dyn Error>> {
+    let mut program = Program::from(read_program(io::stdin().lock())?);
+    program.set_limits(limits);
+    if let Some(mode) = loops {
+        program.detect_loops(mode);
     }
+    let mut input = VecDeque::from(vec![system_id]);
 
-    fn jump_if_true(&mut self, parameters: Vec<Parameter>) {
-        if self.get_parameter(&parameters[0], self.index + 1) != 0 {
-            self.index = self.get_parameter(&parameters[1], self.index + 2) as usize;
-        } else {
-            self.index += 3;
-        }
+    if let Err(error) = program.run_with(&mut input, &mut StderrOutput) {
+        eprintln!("Program failed at instruction {}: {}", program.index, error);
+        std::process::exit(1);
     }
 
-    fn jump_if_false(&mut self, parameters: Vec<Parameter>) {
-        if self.get_parameter(&parameters[0], self.index + 1) == 0 {
-            self.index = self.get_parameter(&parameters[1], self.index + 2) as usize;
-        } else {
-            self.index += 3;
-        }
+    Ok(())
+}
+
+fn trace(system_id: i64) -> Result<(), Box<dyn Error>> {
+    let mut program = Program::from(read_program(io::stdin().lock())?);
+    let mut input = VecDeque::from(vec![system_id]);
+    let mut tracer = JsonLinesTracer::new(BufWriter::new(io::stdout()));
+
+    // Flush before exiting, the last instructions matter most on failure.
+    let result = program.run_traced(&mut input, &mut StderrOutput, &mut tracer);
+    tracer.into_inner().flush()?;
+    if let Err(error) = result {
+        eprintln!("Program failed at instruction {}: {}", program.index, error);
+        std::process::exit(1);
     }
 
-    fn less_then(&mut self, parameters: Vec<Parameter>) {
-        let first = self.get_parameter(&parameters[0], self.index + 1);
-        let second = self.get_parameter(&parameters[1], self.index + 2);
-        let destination = self.value(self.index + 3);
+    Ok(())
+}
 
-        let output = if first < second {
-            1
-        } else {
-            0
-        };
+fn profile(path: &str, system_id: i64) -> Result<(), Box<dyn Error>> {
+    let mut program = Program::from(read_program(io::stdin().lock())?);
+    let mut input = VecDeque::from(vec![system_id]);
+    let mut profiler = Profiler::new();
 
-        self.write(destination as usize, output);
-        self.index += 4;
+    let result = program.run_traced(&mut input, &mut StderrOutput, &mut profiler);
+    profiler.stop();
+    if let Err(error) = result {
+        eprintln!("Program failed at instruction {}: {}", program.index, error);
     }
 
-    fn equals(&mut self, parameters: Vec<Parameter>) {
-        let first = self.get_parameter(&parameters[0], self.index + 1);
-        let second = self.get_parameter(&parameters[1], self.index + 2);
-        let destination = self.value(self.index + 3);
+    eprint!("{}", profiler.report(20));
+    fs::write(path, profiler.folded())?;
 
-        let output = if first == second {
-            1
-        } else {
-            0
-        };
+    Ok(())
+}
+
+fn amplify(mode: Mode) -> Result<(), Box<dyn Error>> {
+    let program = Program::from(read_program(io::stdin().lock())?);
+    let started = Instant::now();
 
-        self.write(destination as usize, output);
-        self.index += 4;
+    match amplifier::find_max_signal(&program, &mode.default_phases(), mode)? {
+        Some(best) => eprintln!("Phase settings {:?} produce thruster signal: {}", best.phases, best.signal),
+        None => eprintln!("No phase settings to try"),
     }
+    eprintln!("Searched in {:?}", started.elapsed());
+
+    Ok(())
 }
 
-impl From<Vec<i32>> for Program {
-    fn from(data: Vec<i32>) -> Program {
-        Program {
-            data: data,
-            index: 0,
+fn simulate_network(nodes: usize) -> Result<(), Box<dyn Error>> {
+    let program = Program::from(read_program(io::stdin().lock())?);
+    let mut network = Network::new(&program, nodes).with_nat(Nat::default());
+    let mut first_received = None;
+    let mut last_delivered = None;
+
+    while network.ticks() < MAX_NETWORK_TICKS {
+        for event in network.tick()? {
+            match event {
+                network::Event::NatReceived(packet) if first_received.is_none() => {
+                    eprintln!("First Y sent to the NAT: {}", packet.y);
+                    first_received = Some(packet);
+                },
+                network::Event::NatDelivered(packet) => {
+                    if last_delivered == Some(packet.y) {
+                        eprintln!("First Y delivered by the NAT twice in a row: {}", packet.y);
+                        eprintln!("Network ran for {} ticks", network.ticks());
+                        return Ok(());
+                    }
+
+                    last_delivered = Some(packet.y);
+                },
+                _ => {},
+            }
         }
     }
-}
-
-impl std::ops::Index<usize> for Program {
-    type Output = i32;
 
-    fn index(&self, index: usize) -> &Self::Output {
-        &self.data[index]
-    }
+    Err(format!("no Y value was delivered by the NAT twice in a row within {} ticks", MAX_NETWORK_TICKS).into())
 }
 
-fn main() -> Result<(), std::io::Error> {
-    let mut program: Program = Program::from(io::stdin().lock().lines()
-        .map(|line| parse_program_into_instructions(&line.unwrap()))
-        .flatten()
-        .collect::<Vec<i32>>()
-    );
-
-    let input = 5;
-
-    loop {
-        let instruction = Instruction::parse(program[program.index]).unwrap();
-        match instruction.opcode {
-            Opcode::Input => {
-                program.index += 1;
-                program.write(program.value(program.index) as usize, input);
-                program.index += 1;
-            },
-            Opcode::Addition => {
-                program.index += 1;
-                program.addition(instruction.parameters);
-                program.index += 3;
-            },
-            Opcode::Multiplication => {
-                program.index += 1;
-                program.multiply(instruction.parameters);
-                program.index += 3;
-            },
-            Opcode::JumpIfTrue => {
-                program.jump_if_true(instruction.parameters);
-            },
-            Opcode::JumpIfFalse => {
-                program.jump_if_false(instruction.parameters);
-            },
-            Opcode::LessThen => {
-                program.less_then(instruction.parameters);
-            },
-            Opcode::Equals => {
-                program.equals(instruction.parameters);
-            },
-            Opcode::Output => {
-                program.index += 1;
-                eprintln!("{}", program.value(program.value(program.index) as usize));
-                program.index += 1;
+fn checkpoint(path: &str, steps: u64, system_id: i64) -> Result<(), Box<dyn Error>> {
+    let mut program = Program::from(read_program(io::stdin().lock())?);
+    let mut outputs = vec![];
+    let mut executed = 0;
+    program.push_input(system_id);
+
+    while executed < steps {
+        match program.step() {
+            Ok(Some(Status::Output(value))) => {
+                eprintln!("{}", value);
+                outputs.push(value);
             },
-            Opcode::Exit => {
+            Ok(Some(Status::NeedsInput)) | Ok(Some(Status::Halted)) => break,
+            Ok(None) => {},
+            Err(error) => {
+                eprintln!("Program failed at instruction {}: {}", program.index, error);
                 break;
             },
         }
+
+        executed += 1;
     }
 
+    fs::write(path, Snapshot::capture(&program, &outputs)?.to_string())?;
+    eprintln!("Saved snapshot after {} instructions to {}", executed, path);
+
     Ok(())
 }
 
-#[derive(Debug, PartialEq)]
-enum ParameterMode {
-    Position,
-    Immediate,
-}
+fn resume(path: &str) -> Result<(), Box<dyn Error>> {
+    let mut program = Snapshot::parse(&fs::read_to_string(path)?)?.restore();
 
-impl From<&u32> for ParameterMode {
-    fn from(input: &u32) -> ParameterMode {
-        if input == &0 {
-            ParameterMode::Position
-        } else if input == &1 {
-            ParameterMode::Immediate
-        } else {
-            panic!("Unsupported parameter mode")
-        }
+    if let Err(error) = program.run_with(&mut LineInput::stdin(), &mut StderrOutput) {
+        eprintln!("Program failed at instruction {}: {}", program.index, error);
+        std::process::exit(1);
     }
-}
 
-#[derive(Debug, PartialEq)]
-enum Opcode {
-    Addition,
-    Multiplication,
-    JumpIfTrue,
-    JumpIfFalse,
-    LessThen,
-    Equals,
-    Input,
-    Output,
-    Exit,
-}
-
-impl From<u32> for Opcode {
-    fn from(number: u32) -> Opcode {
-        match number {
-            1 => Self::Addition,
-            2 => Self::Multiplication,
-            3 => Self::Input,
-            4 => Self::Output,
-            5 => Self::JumpIfTrue,
-            6 => Self::JumpIfFalse,
-            7 => Self::LessThen,
-            8 => Self::Equals,
-            99 => Self::Exit,
-            opcode => panic!("Unknown opcode: {}", opcode),
-        }
-    }
+    Ok(())
 }
 
-#[derive(Debug, PartialEq)]
-struct Instruction {
-    opcode: Opcode,
-    parameters: Vec<Parameter>,
-}
+fn disassemble() -> Result<(), Box<dyn Error>> {
+    print!("{}", disasm::listing(&read_program(io::stdin().lock())?));
 
-#[derive(Debug, PartialEq)]
-struct Parameter {
-    mode: ParameterMode,
-    position: usize,
+    Ok(())
 }
 
-impl Instruction {
-    fn normalize(input: i32) -> Result<[u32; 4], &'static str> {
-        if input > 99999 {
-            return Err("overflow opcode")
-        }
+fn graph(format: GraphFormat) -> Result<(), Box<dyn Error>> {
+    let cfg = Cfg::build(&read_program(io::stdin().lock())?);
 
-        let mut iterator: Vec<u32> = input.to_string()
-            .chars()
-            .map(|s| s.to_digit(10).unwrap())
-            .collect();
-        iterator.reverse();
+    match format {
+        GraphFormat::Dot => print!("{}", cfg.to_dot()),
+        GraphFormat::Json => println!("{}", cfg.to_json()),
+    }
 
-        let mut parameters = [0u32; 5];
-        let mut index = 5;
+    Ok(())
+}
 
-        for value in iterator {
-            parameters[index - 1] = value;
-            index -= 1;
-        }
+fn assemble() -> Result<(), Box<dyn Error>> {
+    let mut source = String::new();
+    io::stdin().read_to_string(&mut source)?;
+    println!("{}", asm::format_program(&asm::assemble(&source)?));
 
-        parameters[3] = format!("{}{}", parameters[3], parameters[4]).parse::<u32>().unwrap();
+    Ok(())
+}
 
-        Ok([
-            parameters[0],
-            parameters[1],
-            parameters[2],
-            parameters[3],
-        ])
+fn ascii(path: &str, script: Option<&str>) -> Result<(), Box<dyn Error>> {
+    let mut program = Program::from(read_program(BufReader::new(File::open(path)?))?);
+    let result = match script {
+        Some(script) => {
+            let script = BufReader::new(File::open(script)?);
+            Console::new(script, io::stdout().lock()).echo(true).run(&mut program)
+        },
+        None => Console::new(io::stdin().lock(), io::stdout().lock()).run(&mut program),
+    };
+
+    if let Err(error) = result {
+        eprintln!("Program failed at instruction {}: {}", program.index, error);
+        std::process::exit(1);
     }
 
-    fn parse(input: i32) -> Result<Instruction, &'static str> {
-        let parameters = Self::normalize(input)?;
-        let opcode = Opcode::from(parameters[3]);
-
-        let parameters = match &opcode {
-            Opcode::Exit => vec![],
-            Opcode::Input | Opcode::Output => {
-                vec![Parameter {
-                    mode: ParameterMode::Position,
-                    position: 0,
-                }]
-            },
-            _ => {
-                parameters[0..=2].into_iter()
-                    .rev()
-                    .map(ParameterMode::from)
-                    .enumerate()
-                    .map(|(position, mode)| Parameter { mode, position })
-                    .collect::<Vec<Parameter>>()
-            },
-        };
+    Ok(())
+}
 
-        Ok(Instruction {
-            opcode,
-            parameters,
-        })
+fn devices(path: &str, seed: Option<u64>) -> Result<(), Box<dyn Error>> {
+    let mut program = Program::from(read_program(BufReader::new(File::open(path)?))?);
+    let seed = match seed {
+        Some(seed) => seed,
+        None => SystemTime::now().duration_since(UNIX_EPOCH)?.as_nanos() as u64,
+    };
+    let screen = Arc::new(Framebuffer::new(40, 6));
+
+    program.map_device(1000, Arc::new(CycleCounter::new()))?;
+    program.map_device(1001, Arc::new(RandomSource::new(seed)))?;
+    program.map_device(1002, Arc::new(ConsolePort::new(io::stdout())))?;
+    program.map_device(1100, screen.clone())?;
+
+    if let Err(error) = program.run_with(&mut LineInput::stdin(), &mut StderrOutput) {
+        eprintln!("Program failed at instruction {}: {}", program.index, error);
+        std::process::exit(1);
     }
+    print!("{}", screen.render());
+
+    Ok(())
+}
+
+fn debug(path: &str) -> Result<(), Box<dyn Error>> {
+    let program = Program::from(read_program(BufReader::new(File::open(path)?))?);
+    Debugger::new(program).repl(io::stdin().lock(), io::stdout())?;
+
+    Ok(())
 }
 
-fn parse_program_into_instructions(raw: &str) -> Vec<i32> {
-    raw.split(',').map(|s| s.parse::<i32>().unwrap()).collect()
+fn main() {
+    let command = match parse_arguments(env::args().skip(1)) {
+        Ok(command) => command,
+        Err(error) => {
+            eprintln!("{}\n\n{}", error, USAGE);
+            std::process::exit(2);
+        },
+    };
+
+    let result = match command {
+        Command::Run { system_id, limits, loops } => run(system_id, limits, loops),
+        Command::Disassemble => disassemble(),
+        Command::Assemble => assemble(),
+        Command::Debug(path) => debug(&path),
+        Command::Trace(system_id) => trace(system_id),
+        Command::Amplify(mode) => amplify(mode),
+        Command::Network(nodes) => simulate_network(nodes),
+        Command::Checkpoint { path, steps, system_id } => checkpoint(&path, steps, system_id),
+        Command::Resume(path) => resume(&path),
+        Command::Graph(format) => graph(format),
+        Command::Ascii { path, script } => ascii(&path, script.as_deref()),
+        Command::Devices { path, seed } => devices(&path, seed),
+        Command::Profile { path, system_id } => profile(&path, system_id),
+    };
+
+    if let Err(error) = result {
+        eprintln!("{}", error);
+        std::process::exit(1);
+    }
 }
 
 #[cfg(test)]
 mod test {
     use super::*;
 
-    #[test]
-    fn it_decodes_raw_program_instructions() {
-        let raw_line: &str = "1002,4,3,4,33";
-        let instructions = parse_program_into_instructions(&raw_line);
-
-        assert_eq!(instructions, vec![1002, 4, 3, 4, 33]);
+    fn parse(arguments: &str) -> Result<Command, String> {
+        parse_arguments(arguments.split_whitespace().map(String::from)).map_err(|error| error.to_string())
     }
 
     #[test]
-    fn it_can_normalize_instruction() {
-        assert_eq!(Instruction::normalize(111112), Err("overflow opcode"));
-        assert_eq!(Instruction::normalize(11112), Ok([1, 1, 1, 12]));
-        assert_eq!(Instruction::normalize(1003), Ok([0, 1, 0, 3]));
-        assert_eq!(Instruction::normalize(99), Ok([0, 0, 0, 99]));
-        assert_eq!(Instruction::normalize(1003), Ok([0, 1, 0, 3]));
+    fn it_parses_run_options() {
+        assert_eq!(parse(""), Ok(Command::Run { system_id: 5, limits: Limits::new(), loops: None }));
+        assert_eq!(parse("1 --max-steps 10 --max-memory 20 --max-outputs 3 --timeout 0.5 --detect-loops exact"), Ok(Command::Run {
+            system_id: 1,
+            limits: Limits::new().steps(10).memory(20).outputs(3).timeout(Duration::from_millis(500)),
+            loops: Some(LoopDetection::Exact),
+        }));
+        assert_eq!(parse("--detect-loops heuristic"), Ok(Command::Run {
+            system_id: 5,
+            limits: Limits::new(),
+            loops: Some(LoopDetection::Heuristic),
+        }));
+    }
 
+    #[test]
+    fn it_rejects_bad_run_options() {
+        assert_eq!(parse("--max-steps"), Err("--max-steps needs a value".to_string()));
+        assert_eq!(parse("--detect-loops sometimes"), Err("unknown loop detection mode \"sometimes\"".to_string()));
+        assert_eq!(parse("1 2"), Err("unexpected argument \"2\"".to_string()));
+        assert!(parse("--timeout -1").is_err());
+        assert!(parse("--timeout NaN").is_err());
+        assert!(parse("five").is_err());
     }
 
     #[test]
-    fn it_parses_opcode_instructions() {
-        assert_eq!(Instruction::parse(2), Ok(Instruction {
-            opcode: Opcode::Multiplication,
-            parameters: vec![
-                Parameter {
-                    mode: ParameterMode::Position,
-                    position: 0,
-                },
-                Parameter {
-                    mode: ParameterMode::Position,
-                    position: 1,
-                },
-                Parameter {
-                    mode: ParameterMode::Position,
-                    position: 2,
-                },
-            ],
+    fn it_parses_every_subcommand() {
+        assert_eq!(parse("disasm"), Ok(Command::Disassemble));
+        assert_eq!(parse("asm"), Ok(Command::Assemble));
+        assert_eq!(parse("debug day5.txt"), Ok(Command::Debug("day5.txt".to_string())));
+        assert_eq!(parse("trace"), Ok(Command::Trace(5)));
+        assert_eq!(parse("trace 1"), Ok(Command::Trace(1)));
+        assert_eq!(parse("amplify"), Ok(Command::Amplify(Mode::Serial)));
+        assert_eq!(parse("amplify feedback"), Ok(Command::Amplify(Mode::Feedback)));
+        assert_eq!(parse("network"), Ok(Command::Network(50)));
+        assert_eq!(parse("network 3"), Ok(Command::Network(3)));
+        assert_eq!(parse("checkpoint state.txt 100"), Ok(Command::Checkpoint {
+            path: "state.txt".to_string(),
+            steps: 100,
+            system_id: 5,
         }));
-
-        assert_eq!(Instruction::parse(0102), Ok(Instruction {
-            opcode: Opcode::Multiplication,
-            parameters: vec![
-                Parameter {
-                    mode: ParameterMode::Immediate,
-                    position: 0,
-                },
-                Parameter {
-                    mode: ParameterMode::Position,
-                    position: 1,
-                },
-                Parameter {
-                    mode: ParameterMode::Position,
-                    position: 2,
-                },
-            ],
+        assert_eq!(parse("checkpoint state.txt 100 1"), Ok(Command::Checkpoint {
+            path: "state.txt".to_string(),
+            steps: 100,
+            system_id: 1,
         }));
-
-        assert_eq!(Instruction::parse(1002), Ok(Instruction {
-            opcode: Opcode::Multiplication,
-            parameters: vec![
-                Parameter {
-                    mode: ParameterMode::Position,
-                    position: 0,
-                },
-                Parameter {
-                    mode: ParameterMode::Immediate,
-                    position: 1,
-                },
-                Parameter {
-                    mode: ParameterMode::Position,
-                    position: 2,
-                },
-            ],
+        assert_eq!(parse("resume state.txt"), Ok(Command::Resume("state.txt".to_string())));
+        assert_eq!(parse("cfg"), Ok(Command::Graph(GraphFormat::Dot)));
+        assert_eq!(parse("cfg json"), Ok(Command::Graph(GraphFormat::Json)));
+        assert_eq!(parse("ascii game.txt"), Ok(Command::Ascii { path: "game.txt".to_string(), script: None }));
+        assert_eq!(parse("ascii game.txt moves.txt"), Ok(Command::Ascii {
+            path: "game.txt".to_string(),
+            script: Some("moves.txt".to_string()),
         }));
+        assert_eq!(parse("devices demo.txt"), Ok(Command::Devices { path: "demo.txt".to_string(), seed: None }));
+        assert_eq!(parse("devices demo.txt 7"), Ok(Command::Devices { path: "demo.txt".to_string(), seed: Some(7) }));
+        assert_eq!(parse("profile stacks.txt"), Ok(Command::Profile { path: "stacks.txt".to_string(), system_id: 5 }));
+        assert_eq!(parse("profile stacks.txt 1"), Ok(Command::Profile { path: "stacks.txt".to_string(), system_id: 1 }));
+    }
 
-        assert_eq!(Instruction::parse(3), Ok(Instruction {
-            opcode: Opcode::Input,
-            parameters: vec![
-                Parameter {
-                    mode: ParameterMode::Position,
-                    position: 0,
-                },
-            ],
-        }));
+    #[test]
+    fn it_rejects_incomplete_or_unknown_subcommand_arguments() {
+        assert_eq!(parse("debug"), Err("usage: dec05 debug FILE".to_string()));
+        assert_eq!(parse("checkpoint state.txt"), Err("usage: dec05 checkpoint FILE STEPS [SYSTEM_ID]".to_string()));
+        assert_eq!(parse("resume"), Err("usage: dec05 resume FILE".to_string()));
+        assert_eq!(parse("ascii"), Err("usage: dec05 ascii FILE [SCRIPT]".to_string()));
+        assert_eq!(parse("devices"), Err("usage: dec05 devices FILE [SEED]".to_string()));
+        assert_eq!(parse("profile"), Err("usage: dec05 profile FILE [SYSTEM_ID]".to_string()));
+        assert_eq!(parse("amplify parallel"), Err("unknown amplifier mode \"parallel\"".to_string()));
+        assert_eq!(parse("cfg svg"), Err("unknown graph format \"svg\"".to_string()));
+        assert!(parse("trace x").is_err());
+        assert!(parse("network -1").is_err());
+        assert!(parse("checkpoint state.txt many").is_err());
+        assert!(parse("devices demo.txt seed").is_err());
     }
 }
@@ -0,0 +1,99 @@
+use std::convert::TryFrom;
+
+use criterion::{black_box, criterion_group, criterion_main, Criterion};
+
+use intcode::asm::assemble;
+use intcode::{DecodeError, Instruction, Opcode, Parameter, ParameterMode, Program, Status};
+
+/// Sums `i * i` for every `i` below the input, touching every parameter mode
+/// and both jumps on each iteration.
+const SUM_OF_SQUARES: &str = "
+            in [count]
+            arb #total
+    loop:   mul [i], [i], [square]
+            add [rb], [square], [rb]
+            add [i], #1, [i]
+            lt [i], [count], [more]
+            jnz [more], #loop
+            jz #0, #done
+    done:   out [total]
+            hlt
+    count:  .data 0
+    i:      .data 0
+    square: .data 0
+    more:   .data 0
+    total:  .data 0
+";
+
+/// The string based decoder `Instruction::parse` used to be, kept to show
+/// what the arithmetic decoder saves.
+fn legacy_parse(input: i64) -> Result<(Opcode, Vec<Parameter>), DecodeError> {
+    if !(0..=99999).contains(&input) {
+        return Err(DecodeError::Overflow(input));
+    }
+
+    let mut digits: Vec<u32> = input.to_string()
+        .chars()
+        .map(|s| s.to_digit(10).unwrap())
+        .collect();
+    digits.reverse();
+
+    let mut normalized = [0u32; 5];
+    for (index, value) in digits.into_iter().enumerate() {
+        normalized[4 - index] = value;
+    }
+    normalized[3] = format!("{}{}", normalized[3], normalized[4]).parse::<u32>().unwrap();
+
+    let opcode = Opcode::try_from(normalized[3])?;
+    let parameters = normalized[0..=2].iter()
+        .rev()
+        .take(opcode.arity())
+        .map(|mode| ParameterMode::try_from(*mode))
+        .enumerate()
+        .map(|(position, mode)| mode.map(|mode| Parameter { mode, position }))
+        .collect::<Result<Vec<Parameter>, DecodeError>>()?;
+
+    Ok((opcode, parameters))
+}
+
+fn run(program: &Program, count: i64) -> i64 {
+    let mut program = program.clone();
+    program.push_input(count);
+
+    match program.run() {
+        Ok(Status::Output(total)) => total,
+        status => panic!("unexpected status {:?}", status),
+    }
+}
+
+fn decode(c: &mut Criterion) {
+    let words = [1, 2, 1002, 1101, 21201, 3, 104, 1105, 1006, 1007, 1108, 109, 204, 99];
+    let mut group = c.benchmark_group("decode");
+
+    group.bench_function("legacy", |b| b.iter(|| {
+        for word in words.iter() {
+            black_box(legacy_parse(black_box(*word)).unwrap());
+        }
+    }));
+    group.bench_function("arithmetic", |b| b.iter(|| {
+        for word in words.iter() {
+            black_box(Instruction::parse(black_box(*word)).unwrap());
+        }
+    }));
+    group.finish();
+}
+
+fn execute(c: &mut Criterion) {
+    let program = Program::from(assemble(SUM_OF_SQUARES).unwrap());
+    let mut uncached = program.clone();
+    uncached.set_decode_cache(false);
+    assert_eq!(run(&program, 100_000), run(&uncached, 100_000));
+
+    let mut group = c.benchmark_group("sum of squares");
+    group.bench_function("uncached", |b| b.iter(|| run(&uncached, black_box(100_000))));
+    group.bench_function("cached", |b| b.iter(|| run(&program, black_box(100_000))));
+    group.finish();
+}
+
+criterion_group!(benches, decode, execute);
+criterion_main!(benches);
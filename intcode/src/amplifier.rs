@@ -0,0 +1,184 @@
+//! Chains of amplifiers, each running a copy of the same program.
+//!
+//! Every amplifier first reads its phase setting and then the signal
+//! produced by the previous amplifier; the first one receives the initial
+//! input signal.
+
+use crate::error::IntcodeError;
+use crate::program::{Program, Status};
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Mode {
+    /// The signal passes through every amplifier once.
+    Serial,
+    /// The last amplifier feeds back into the first until the amplifiers halt.
+    Feedback,
+}
+
+impl Mode {
+    /// Phase settings the puzzle allows for the mode.
+    pub fn default_phases(&self) -> Vec<i64> {
+        match self {
+            Self::Serial => (0..=4).collect(),
+            Self::Feedback => (5..=9).collect(),
+        }
+    }
+}
+
+fn boot(program: &Program, phases: &[i64]) -> Vec<Program> {
+    phases.iter()
+        .map(|phase| {
+            let mut amplifier = program.clone();
+            amplifier.push_input(*phase);
+            amplifier
+        })
+        .collect()
+}
+
+/// Run the chain once for the given phase settings and return the signal
+/// leaving the last amplifier.
+pub fn run(program: &Program, phases: &[i64], signal: i64, mode: Mode) -> Result<i64, IntcodeError> {
+    if phases.is_empty() {
+        return Err(IntcodeError::MissingOutput);
+    }
+
+    let mut amplifiers = boot(program, phases);
+    let mut signal = signal;
+    let mut thrusters = None;
+
+    loop {
+        for (position, amplifier) in amplifiers.iter_mut().enumerate() {
+            amplifier.push_input(signal);
+
+            match amplifier.run()? {
+                Status::Output(value) => signal = value,
+                Status::Halted => return thrusters.ok_or(IntcodeError::MissingOutput),
+                Status::NeedsInput => return Err(IntcodeError::InputUnavailable),
+            }
+
+            if position + 1 == phases.len() {
+                thrusters = Some(signal);
+            }
+        }
+
+        if mode == Mode::Serial {
+            return thrusters.ok_or(IntcodeError::MissingOutput);
+        }
+    }
+}
+
+/// Every ordering of `values`, generated with Heap's algorithm. There are
+/// none without values, so searching no phase settings finds nothing.
+pub fn permutations(values: &[i64]) -> Vec<Vec<i64>> {
+    if values.is_empty() {
+        return vec![];
+    }
+
+    let mut values = values.to_vec();
+    let mut counters = vec![0; values.len()];
+    let mut permutations = vec![values.clone()];
+    let mut position = 0;
+
+    while position < values.len() {
+        if counters[position] < position {
+            let swap = if position % 2 == 0 { 0 } else { counters[position] };
+            values.swap(swap, position);
+            permutations.push(values.clone());
+
+            counters[position] += 1;
+            position = 0;
+        } else {
+            counters[position] = 0;
+            position += 1;
+        }
+    }
+
+    permutations
+}
+
+/// Phase settings producing the strongest thruster signal.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Best {
+    pub phases: Vec<i64>,
+    pub signal: i64,
+}
+
+/// Try every permutation of `phases` and keep the one with the highest
+/// thruster signal, starting from an input signal of zero.
+pub fn find_max_signal(program: &Program, phases: &[i64], mode: Mode) -> Result<Option<Best>, IntcodeError> {
+    let mut best: Option<Best> = None;
+
+    for permutation in permutations(phases) {
+        let signal = run(program, &permutation, 0, mode)?;
+
+        if best.as_ref().is_none_or(|best| signal > best.signal) {
+            best = Some(Best {
+                phases: permutation,
+                signal,
+            });
+        }
+    }
+
+    Ok(best)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::program::parse_program_into_instructions;
+
+    fn program(source: &str) -> Program {
+        Program::from(parse_program_into_instructions(source).unwrap())
+    }
+
+    #[test]
+    fn it_generates_every_permutation() {
+        let mut permutations = permutations(&[1, 2, 3]);
+        permutations.sort();
+
+        assert_eq!(permutations, vec![
+            vec![1, 2, 3],
+            vec![1, 3, 2],
+            vec![2, 1, 3],
+            vec![2, 3, 1],
+            vec![3, 1, 2],
+            vec![3, 2, 1],
+        ]);
+    }
+
+    #[test]
+    fn it_tries_nothing_without_phase_settings() {
+        let program = program("3,15,3,16,1002,16,10,16,1,16,15,15,4,15,99,0,0");
+
+        assert_eq!(permutations(&[]), Vec::<Vec<i64>>::new());
+        assert_eq!(find_max_signal(&program, &[], Mode::Serial), Ok(None));
+    }
+
+    #[test]
+    fn it_finds_the_maximum_serial_signal() {
+        let program = program("3,15,3,16,1002,16,10,16,1,16,15,15,4,15,99,0,0");
+
+        assert_eq!(run(&program, &[4, 3, 2, 1, 0], 0, Mode::Serial), Ok(43210));
+        assert_eq!(find_max_signal(&program, &Mode::Serial.default_phases(), Mode::Serial), Ok(Some(Best {
+            phases: vec![4, 3, 2, 1, 0],
+            signal: 43210,
+        })));
+    }
+
+    #[test]
+    fn it_finds_the_maximum_feedback_signal() {
+        let program = program(
+            "3,26,1001,26,-4,26,3,27,1002,27,2,27,1,27,26,27,4,27,1001,28,-1,28,1005,28,6,99,0,0,5"
+        );
+
+        assert_eq!(find_max_signal(&program, &Mode::Feedback.default_phases(), Mode::Feedback), Ok(Some(Best {
+            phases: vec![9, 8, 7, 6, 5],
+            signal: 139629729,
+        })));
+    }
+
+    #[test]
+    fn it_reports_amplifiers_without_output() {
+        assert_eq!(run(&program("3,0,3,0,99"), &[0], 0, Mode::Serial), Err(IntcodeError::MissingOutput));
+    }
+}
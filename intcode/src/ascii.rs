@@ -0,0 +1,131 @@
+//! Console for programs that talk in ASCII: output codes below 128 are
+//! printed as characters, anything else as a number on its own line, and
+//! every line read from the input is fed to the program as character codes
+//! ending in a newline.
+
+use std::io::{BufRead, Write};
+
+use crate::error::IntcodeError;
+use crate::program::{Program, Status};
+
+/// Character codes for `text`, failing on the first non-ASCII character.
+pub fn encode(text: &str) -> Result<Vec<i64>, IntcodeError> {
+    text.chars()
+        .enumerate()
+        .map(|(column, character)| {
+            if character.is_ascii() {
+                Ok(character as i64)
+            } else {
+                Err(IntcodeError::Parse { column: column + 1, token: character.to_string() })
+            }
+        })
+        .collect()
+}
+
+/// Render output values the way the console prints them.
+pub fn decode(values: &[i64]) -> String {
+    let mut text = String::new();
+    for value in values {
+        match value {
+            0..=127 => text.push(*value as u8 as char),
+            _ => text.push_str(&format!("{}\n", value)),
+        }
+    }
+
+    text
+}
+
+fn device(error: std::io::Error) -> IntcodeError {
+    IntcodeError::Device { message: error.to_string() }
+}
+
+pub struct Console<R, W> {
+    input: R,
+    output: W,
+    echo: bool,
+}
+
+impl<R: BufRead, W: Write> Console<R, W> {
+    pub fn new(input: R, output: W) -> Console<R, W> {
+        Console { input, output, echo: false }
+    }
+
+    /// Write every line read to the output as well, so scripted sessions
+    /// read like interactive ones.
+    pub fn echo(mut self, echo: bool) -> Console<R, W> {
+        self.echo = echo;
+        self
+    }
+
+    /// Feed the next input line to `program`. Fails with
+    /// `IntcodeError::InputUnavailable` once the input is exhausted.
+    fn read_line(&mut self, program: &mut Program) -> Result<(), IntcodeError> {
+        self.output.flush().map_err(device)?;
+
+        let mut line = String::new();
+        if self.input.read_line(&mut line).map_err(device)? == 0 {
+            return Err(IntcodeError::InputUnavailable);
+        }
+
+        let line = line.trim_end_matches(['\r', '\n'].as_ref());
+        let codes = encode(line)?;
+        if self.echo {
+            writeln!(self.output, "{}", line).map_err(device)?;
+        }
+
+        for code in codes {
+            program.push_input(code);
+        }
+        program.push_input('\n' as i64);
+
+        Ok(())
+    }
+
+    /// Run `program` until it halts.
+    pub fn run(&mut self, program: &mut Program) -> Result<(), IntcodeError> {
+        loop {
+            match program.run()? {
+                Status::NeedsInput => self.read_line(program)?,
+                Status::Output(value) => {
+                    self.output.write_all(decode(&[value]).as_bytes()).map_err(device)?;
+                },
+                Status::Halted => return self.output.flush().map_err(device),
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    /// Prints every character it reads until a newline, then 1000.
+    fn program() -> Program {
+        Program::from(vec![3, 100, 4, 100, 1008, 100, 10, 101, 1006, 101, 0, 104, 1000, 99])
+    }
+
+    fn run(input: &str, echo: bool) -> (Result<(), IntcodeError>, String) {
+        let mut output = vec![];
+        let result = Console::new(input.as_bytes(), &mut output).echo(echo).run(&mut program());
+
+        (result, String::from_utf8(output).unwrap())
+    }
+
+    #[test]
+    fn it_converts_between_text_and_codes() {
+        assert_eq!(encode("Hi!"), Ok(vec![72, 105, 33]));
+        assert_eq!(encode("hé"), Err(IntcodeError::Parse { column: 2, token: "é".to_string() }));
+        assert_eq!(decode(&[72, 105, 10, 19349964, -1]), "Hi\n19349964\n-1\n");
+    }
+
+    #[test]
+    fn it_feeds_lines_and_prints_text_and_numbers() {
+        assert_eq!(run("hey\r\nignored\n", false), (Ok(()), "hey\n1000\n".to_string()));
+        assert_eq!(run("hey\n", true), (Ok(()), "hey\nhey\n1000\n".to_string()));
+    }
+
+    #[test]
+    fn it_fails_when_the_input_runs_out() {
+        assert_eq!(run("", false), (Err(IntcodeError::InputUnavailable), String::new()));
+    }
+}
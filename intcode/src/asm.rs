@@ -0,0 +1,334 @@
+//! Assembler for a small intcode assembly language.
+//!
+//! ```text
+//! ; read a number and print it twice
+//! start:  in [value]
+//!         out [value]
+//!         out [rb+0]
+//!         jz #0, #start
+//!         hlt
+//! value:  .data 0
+//! ```
+//!
+//! Every line holds an optional `label:`, an optional statement and an
+//! optional `;` comment. Statements use the mnemonics of `Opcode::mnemonic`
+//! or the `.data` directive, which emits its comma separated values as is.
+//! Operands are written like the disassembler prints them: `[x]` for
+//! position mode, `#x` for immediate mode and `[rb]`, `[rb+x]` or `[rb-x]`
+//! for relative mode, where `x` is either a number or a label.
+
+use std::collections::HashMap;
+use std::fmt;
+
+use crate::instruction::{Opcode, ParameterMode};
+
+/// Assembly failures, each carrying the one-based source line.
+#[derive(Debug, PartialEq, Clone)]
+pub enum AsmError {
+    UnknownMnemonic { line: usize, mnemonic: String },
+    OperandCount { line: usize, expected: usize, found: usize },
+    InvalidOperand { line: usize, operand: String },
+    ImmediateDestination { line: usize, operand: String },
+    InvalidLabel { line: usize, label: String },
+    DuplicateLabel { line: usize, label: String },
+    UndefinedLabel { line: usize, label: String },
+}
+
+impl fmt::Display for AsmError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::UnknownMnemonic { line, mnemonic } => {
+                write!(f, "line {}: unknown mnemonic {:?}", line, mnemonic)
+            },
+            Self::OperandCount { line, expected, found } => {
+                write!(f, "line {}: expected {} operands, found {}", line, expected, found)
+            },
+            Self::InvalidOperand { line, operand } => {
+                write!(f, "line {}: invalid operand {:?}", line, operand)
+            },
+            Self::ImmediateDestination { line, operand } => {
+                write!(f, "line {}: cannot write to immediate operand {:?}", line, operand)
+            },
+            Self::InvalidLabel { line, label } => write!(f, "line {}: invalid label {:?}", line, label),
+            Self::DuplicateLabel { line, label } => write!(f, "line {}: label {:?} is already defined", line, label),
+            Self::UndefinedLabel { line, label } => write!(f, "line {}: undefined label {:?}", line, label),
+        }
+    }
+}
+
+impl std::error::Error for AsmError {}
+
+/// A number, or a label resolved to its address in the second pass.
+#[derive(Debug, PartialEq, Clone)]
+enum Value {
+    Number(i64),
+    Label(String),
+    /// A label subtracted from the relative base, as in `[rb-label]`.
+    NegatedLabel(String),
+}
+
+#[derive(Debug, PartialEq, Clone)]
+enum Statement {
+    Instruction { opcode: Opcode, operands: Vec<(ParameterMode, Value)> },
+    Data(Vec<Value>),
+}
+
+impl Statement {
+    fn size(&self) -> usize {
+        match self {
+            Self::Instruction { operands, .. } => 1 + operands.len(),
+            Self::Data(values) => values.len(),
+        }
+    }
+}
+
+fn is_identifier(token: &str) -> bool {
+    let mut characters = token.chars();
+
+    match characters.next() {
+        Some(first) if first.is_ascii_alphabetic() || first == '_' => {
+            characters.all(|character| character.is_ascii_alphanumeric() || character == '_')
+        },
+        _ => false,
+    }
+}
+
+fn parse_value(line: usize, token: &str) -> Result<Value, AsmError> {
+    let token = token.trim();
+
+    if let Ok(number) = token.parse::<i64>() {
+        Ok(Value::Number(number))
+    } else if is_identifier(token) {
+        Ok(Value::Label(token.to_string()))
+    } else {
+        Err(AsmError::InvalidOperand { line, operand: token.to_string() })
+    }
+}
+
+fn parse_operand(line: usize, token: &str) -> Result<(ParameterMode, Value), AsmError> {
+    let invalid = || AsmError::InvalidOperand { line, operand: token.to_string() };
+
+    if let Some(immediate) = token.strip_prefix('#') {
+        return Ok((ParameterMode::Immediate, parse_value(line, immediate)?));
+    }
+
+    let inner = token.strip_prefix('[')
+        .and_then(|rest| rest.strip_suffix(']'))
+        .ok_or_else(invalid)?
+        .trim();
+
+    match inner.strip_prefix("rb") {
+        Some("") => Ok((ParameterMode::Relative, Value::Number(0))),
+        Some(offset) if !is_identifier(inner) => {
+            let offset = offset.trim();
+            let value = if let Some(positive) = offset.strip_prefix('+') {
+                parse_value(line, positive)?
+            } else if let Some(negative) = offset.strip_prefix('-') {
+                let negative = negative.trim();
+                if is_identifier(negative) {
+                    Value::NegatedLabel(negative.to_string())
+                } else {
+                    parse_value(line, &format!("-{}", negative))?
+                }
+            } else {
+                return Err(invalid());
+            };
+
+            Ok((ParameterMode::Relative, value))
+        },
+        _ => Ok((ParameterMode::Position, parse_value(line, inner)?)),
+    }
+}
+
+fn split_operands(text: &str) -> Vec<&str> {
+    if text.trim().is_empty() {
+        vec![]
+    } else {
+        text.split(',').map(str::trim).collect()
+    }
+}
+
+fn parse_statement(line: usize, text: &str) -> Result<Statement, AsmError> {
+    let (head, rest) = match text.find(char::is_whitespace) {
+        Some(split) => (&text[..split], &text[split..]),
+        None => (text, ""),
+    };
+    let operands = split_operands(rest);
+
+    if head == ".data" {
+        let values = operands.iter()
+            .map(|token| parse_value(line, token))
+            .collect::<Result<Vec<Value>, AsmError>>()?;
+
+        return Ok(Statement::Data(values));
+    }
+
+    let opcode = Opcode::from_mnemonic(head)
+        .ok_or_else(|| AsmError::UnknownMnemonic { line, mnemonic: head.to_string() })?;
+
+    if operands.len() != opcode.arity() {
+        return Err(AsmError::OperandCount {
+            line,
+            expected: opcode.arity(),
+            found: operands.len(),
+        });
+    }
+
+    let operands = operands.iter()
+        .map(|token| parse_operand(line, token))
+        .collect::<Result<Vec<(ParameterMode, Value)>, AsmError>>()?;
+
+    if let Some(position) = opcode.write_parameter() {
+        if operands[position].0 == ParameterMode::Immediate {
+            return Err(AsmError::ImmediateDestination {
+                line,
+                operand: split_operands(rest)[position].to_string(),
+            });
+        }
+    }
+
+    Ok(Statement::Instruction { opcode, operands })
+}
+
+fn mode_digit(mode: ParameterMode) -> i64 {
+    match mode {
+        ParameterMode::Position => 0,
+        ParameterMode::Immediate => 1,
+        ParameterMode::Relative => 2,
+    }
+}
+
+/// Assemble source text into intcode memory.
+pub fn assemble(source: &str) -> Result<Vec<i64>, AsmError> {
+    let mut labels: HashMap<String, usize> = HashMap::new();
+    let mut statements: Vec<(usize, Statement)> = vec![];
+    let mut address = 0;
+
+    for (number, raw) in source.lines().enumerate() {
+        let line = number + 1;
+        let mut text = raw.split(';').next().unwrap_or("").trim();
+
+        if let Some(colon) = text.find(':') {
+            let label = text[..colon].trim();
+            if !is_identifier(label) {
+                return Err(AsmError::InvalidLabel { line, label: label.to_string() });
+            }
+            if labels.insert(label.to_string(), address).is_some() {
+                return Err(AsmError::DuplicateLabel { line, label: label.to_string() });
+            }
+
+            text = text[colon + 1..].trim();
+        }
+
+        if !text.is_empty() {
+            let statement = parse_statement(line, text)?;
+            address += statement.size();
+            statements.push((line, statement));
+        }
+    }
+
+    let address_of = |line: usize, label: &String| labels.get(label)
+        .map(|address| *address as i64)
+        .ok_or_else(|| AsmError::UndefinedLabel { line, label: label.clone() });
+    let resolve = |line: usize, value: &Value| match value {
+        Value::Number(number) => Ok(*number),
+        Value::Label(label) => address_of(line, label),
+        Value::NegatedLabel(label) => address_of(line, label).map(|address| -address),
+    };
+
+    let mut memory = Vec::with_capacity(address);
+    for (line, statement) in statements {
+        match statement {
+            Statement::Instruction { opcode, operands } => {
+                let modes = operands.iter()
+                    .rev()
+                    .fold(0, |word, (mode, _)| word * 10 + mode_digit(*mode));
+                memory.push(modes * 100 + opcode.code() as i64);
+
+                for (_, value) in operands.iter() {
+                    memory.push(resolve(line, value)?);
+                }
+            },
+            Statement::Data(values) => {
+                for value in values.iter() {
+                    memory.push(resolve(line, value)?);
+                }
+            },
+        }
+    }
+
+    Ok(memory)
+}
+
+/// Format memory as the comma separated text `parse_program_into_instructions` reads.
+pub fn format_program(memory: &[i64]) -> String {
+    memory.iter()
+        .map(|value| value.to_string())
+        .collect::<Vec<String>>()
+        .join(",")
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::disasm;
+
+    #[test]
+    fn it_assembles_instructions_labels_and_data() {
+        let source = "
+            ; compare the input with eight
+            start:  in [value]          ; read
+                    eq [value], #8, [value]
+                    out [value]
+                    hlt
+            value:  .data -1, start
+        ";
+
+        assert_eq!(assemble(source), Ok(vec![3, 9, 1008, 9, 8, 9, 4, 9, 99, -1, 0]));
+    }
+
+    #[test]
+    fn it_encodes_relative_operands() {
+        assert_eq!(assemble("add [rb+1], #2, [rb-3]"), Ok(vec![21201, 1, 2, -3]));
+        assert_eq!(assemble("arb #5\nout [rb]"), Ok(vec![109, 5, 204, 0]));
+        assert_eq!(assemble("out [rb+end]\nout [rb - end]\nend: hlt"), Ok(vec![204, 4, 204, -4, 99]));
+        assert_eq!(assemble("out [rb - 5]\nout [rb -5]\nout [rb + 5]"), Ok(vec![204, -5, 204, -5, 204, 5]));
+        assert_eq!(assemble("out [rb-]"), Err(AsmError::InvalidOperand { line: 1, operand: "-".to_string() }));
+        assert_eq!(format_program(&[109, 5, 204, 0]), "109,5,204,0");
+    }
+
+    #[test]
+    fn it_reassembles_disassembled_programs() {
+        let memory = vec![1002, 4, 3, 4, 33, 104, -1, 21101, 2, 3, -4, 99];
+        let source: String = disasm::disassemble(&memory).iter()
+            .map(|line| format!("{}\n", line.statement))
+            .collect();
+
+        assert_eq!(assemble(&source), Ok(memory));
+    }
+
+    #[test]
+    fn it_reports_errors_with_line_numbers() {
+        assert_eq!(assemble("hlt\nfoo #1"), Err(AsmError::UnknownMnemonic {
+            line: 2,
+            mnemonic: "foo".to_string(),
+        }));
+        assert_eq!(assemble("add #1, #2"), Err(AsmError::OperandCount { line: 1, expected: 3, found: 2 }));
+        assert_eq!(assemble("\n\nin #4"), Err(AsmError::ImmediateDestination {
+            line: 3,
+            operand: "#4".to_string(),
+        }));
+        assert_eq!(assemble("out [nowhere]"), Err(AsmError::UndefinedLabel {
+            line: 1,
+            label: "nowhere".to_string(),
+        }));
+        assert_eq!(assemble("a: hlt\na: hlt"), Err(AsmError::DuplicateLabel {
+            line: 2,
+            label: "a".to_string(),
+        }));
+        assert_eq!(assemble("out 4"), Err(AsmError::InvalidOperand {
+            line: 1,
+            operand: "4".to_string(),
+        }));
+    }
+}
@@ -0,0 +1,148 @@
+//! Programs running as futures, reading input from a stream and writing
+//! output to a sink, typically the two ends of `futures::channel::mpsc`
+//! channels. Any number of them can share a single-threaded executor with
+//! timers and other tasks:
+//!
+//! ```
+//! use futures::channel::mpsc;
+//! use futures::executor::block_on;
+//! use futures::{SinkExt, StreamExt};
+//! use intcode::{asynchronous, Program};
+//!
+//! let (mut to_program, input) = mpsc::unbounded();
+//! let (output, mut from_program) = mpsc::unbounded();
+//! let mut program = Program::from(vec![3, 0, 102, 2, 0, 0, 4, 0, 99]);
+//!
+//! block_on(async {
+//!     to_program.send(21).await.unwrap();
+//!     asynchronous::run(&mut program, input, output).await.unwrap();
+//!
+//!     assert_eq!(from_program.next().await, Some(42));
+//! });
+//! ```
+//!
+//! Only available with the `async` feature.
+
+use std::future::Future;
+use std::pin::Pin;
+use std::task::{Context, Poll};
+
+use futures::{Sink, SinkExt, Stream, StreamExt};
+
+use crate::error::IntcodeError;
+use crate::program::{Program, Status};
+
+/// Instructions executed between chances for other tasks to run.
+const YIELD_INTERVAL: u64 = 10_000;
+
+/// Returns pending once, so the executor gets to poll other tasks.
+#[derive(Default)]
+struct YieldNow {
+    yielded: bool,
+}
+
+impl Future for YieldNow {
+    type Output = ();
+
+    fn poll(mut self: Pin<&mut Self>, context: &mut Context) -> Poll<()> {
+        if self.yielded {
+            return Poll::Ready(());
+        }
+
+        self.yielded = true;
+        context.waker().wake_by_ref();
+        Poll::Pending
+    }
+}
+
+/// Execute `program` until it halts, awaiting `input` whenever it runs out
+/// of queued input and sending every output value to `output`.
+///
+/// Fails with `IntcodeError::InputUnavailable` when the input stream ends
+/// and with `IntcodeError::OutputUnavailable` when the sink fails. Long
+/// stretches without input or output yield to the executor every so often.
+pub async fn run<I, O>(program: &mut Program, mut input: I, mut output: O) -> Result<(), IntcodeError>
+where
+    I: Stream<Item = i64> + Unpin,
+    O: Sink<i64> + Unpin,
+{
+    let mut steps = 0u64;
+
+    loop {
+        match program.step()? {
+            None => {
+                steps += 1;
+                if steps.is_multiple_of(YIELD_INTERVAL) {
+                    YieldNow::default().await;
+                }
+            },
+            Some(Status::NeedsInput) => match input.next().await {
+                Some(value) => program.push_input(value),
+                None => return Err(IntcodeError::InputUnavailable),
+            },
+            Some(Status::Output(value)) => {
+                output.send(value).await.map_err(|_| IntcodeError::OutputUnavailable)?;
+            },
+            Some(Status::Halted) => return Ok(()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::program::parse_program_into_instructions;
+    use futures::channel::mpsc;
+    use futures::executor::LocalPool;
+    use futures::future;
+    use futures::task::LocalSpawnExt;
+
+    #[test]
+    fn it_runs_a_feedback_loop_on_one_thread() {
+        let program = Program::from(parse_program_into_instructions(
+            "3,26,1001,26,-4,26,3,27,1002,27,2,27,1,27,26,27,4,27,1001,28,-1,28,1005,28,6,99,0,0,5"
+        ).unwrap());
+        let phases = [9, 8, 7, 6, 5];
+
+        let (mut senders, mut receivers): (Vec<_>, Vec<_>) = phases.iter().map(|_| mpsc::unbounded()).unzip();
+        for (sender, phase) in senders.iter().zip(phases.iter()) {
+            sender.unbounded_send(*phase).unwrap();
+        }
+        senders[0].unbounded_send(0).unwrap();
+        senders.rotate_left(1);
+
+        let mut pool = LocalPool::new();
+        let mut first = receivers.remove(0);
+        let amplifiers = std::iter::once(&mut first)
+            .chain(receivers.iter_mut())
+            .zip(senders)
+            .map(|(input, output)| {
+                let mut amplifier = program.clone();
+                async move { run(&mut amplifier, input, output).await }
+            });
+        let finished = pool.run_until(future::join_all(amplifiers));
+
+        assert!(finished.iter().all(Result::is_ok));
+        assert_eq!(first.try_recv(), Ok(139629729));
+    }
+
+    #[test]
+    fn it_lets_other_tasks_run_during_long_computations() {
+        // Counts down from 50000 before printing 1.
+        let mut program = Program::from(vec![1001, 11, -1, 11, 1005, 11, 0, 104, 1, 99, 0, 50000]);
+        let (ticks, mut ticked) = mpsc::unbounded();
+        let (output, mut outputs) = mpsc::unbounded();
+
+        let mut pool = LocalPool::new();
+        pool.spawner().spawn_local(async move {
+            while ticks.unbounded_send(()).is_ok() {
+                YieldNow::default().await;
+            }
+        }).unwrap();
+        let result = pool.run_until(run(&mut program, futures::stream::empty(), output));
+
+        assert_eq!(result, Ok(()));
+        assert_eq!(outputs.try_recv(), Ok(1));
+        assert!(ticked.try_recv().is_ok());
+    }
+}
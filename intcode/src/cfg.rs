@@ -0,0 +1,408 @@
+//! Control flow graph extraction.
+//!
+//! Instructions are discovered by following control flow from address zero,
+//! so words never reached this way are reported as unreachable instead of
+//! being decoded. Blocks end at `jnz`, `jz`, `hlt` and words that do not
+//! decode, and start at jump targets and after conditional jumps.
+//!
+//! Jumps resolve statically when their target is an immediate operand and
+//! are flagged as computed otherwise. Immediate conditions decide the branch
+//! statically too. Writes through position mode operands are tracked: an
+//! operand the analysis relied on that is also written is treated as
+//! unknown, and blocks containing written cells are flagged as
+//! self-modified. Writes through relative mode operands are not tracked.
+
+use std::collections::{BTreeMap, BTreeSet};
+use std::fmt::Write;
+use std::ops::Range;
+
+use crate::disasm::{self, Line, Operand, Statement};
+use crate::instruction::{Opcode, ParameterMode};
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Flow {
+    Next(usize),
+    Jump(usize),
+    Computed,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum EdgeKind {
+    Jump,
+    Fallthrough,
+}
+
+impl EdgeKind {
+    fn name(&self) -> &'static str {
+        match self {
+            Self::Jump => "jump",
+            Self::Fallthrough => "fallthrough",
+        }
+    }
+}
+
+/// Control passing from the block starting at `from` to address `to`. Edges
+/// may point past the end of memory, where execution would fail.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Edge {
+    pub from: usize,
+    pub to: usize,
+    pub kind: EdgeKind,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Block {
+    pub start: usize,
+    /// Address following the last instruction of the block.
+    pub end: usize,
+    pub lines: Vec<Line>,
+    /// The block ends in a jump whose target is only known at runtime.
+    pub computed_jump: bool,
+    /// Some instruction writes into the cells of the block.
+    pub self_modified: bool,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Cfg {
+    pub blocks: Vec<Block>,
+    pub edges: Vec<Edge>,
+    /// Ranges of memory never reached by following control flow.
+    pub unreachable: Vec<Range<usize>>,
+}
+
+#[derive(Default)]
+struct Exploration {
+    lines: BTreeMap<usize, (Line, Vec<Flow>)>,
+    leaders: BTreeSet<usize>,
+    writes: BTreeSet<usize>,
+    /// Operand cells whose immediate values decided control flow.
+    assumed: BTreeSet<usize>,
+}
+
+fn flows(line: &Line, volatile: &BTreeSet<usize>, assumed: &mut BTreeSet<usize>) -> Vec<Flow> {
+    let next = Flow::Next(line.address + line.size());
+
+    let (opcode, operands) = match &line.statement {
+        Statement::Instruction { opcode, operands } => (*opcode, operands),
+        Statement::Data(_) => return vec![],
+    };
+
+    let (condition, target) = match opcode {
+        Opcode::Exit => return vec![],
+        Opcode::JumpIfTrue | Opcode::JumpIfFalse => (operands[0], operands[1]),
+        _ => return vec![next],
+    };
+    let (condition_cell, target_cell) = (line.address + 1, line.address + 2);
+
+    let jump = match target {
+        Operand { mode: ParameterMode::Immediate, value } if !volatile.contains(&target_cell) => {
+            assumed.insert(target_cell);
+            // A negative target always faults, so the jump goes nowhere.
+            match value {
+                value if value < 0 => None,
+                value => Some(Flow::Jump(value as usize)),
+            }
+        },
+        _ => Some(Flow::Computed),
+    };
+
+    let taken = match condition {
+        Operand { mode: ParameterMode::Immediate, value } if !volatile.contains(&condition_cell) => {
+            assumed.insert(condition_cell);
+            Some((value != 0) == (opcode == Opcode::JumpIfTrue))
+        },
+        _ => None,
+    };
+
+    match taken {
+        Some(true) => jump.into_iter().collect(),
+        Some(false) => vec![next],
+        None => jump.into_iter().chain(Some(next)).collect(),
+    }
+}
+
+fn explore(memory: &[i64], volatile: &BTreeSet<usize>) -> Exploration {
+    let mut exploration = Exploration::default();
+    let mut pending = vec![0];
+    exploration.leaders.insert(0);
+
+    while let Some(address) = pending.pop() {
+        if address >= memory.len() || exploration.lines.contains_key(&address) {
+            continue;
+        }
+
+        let line = disasm::decode(memory, address);
+        let flows = flows(&line, volatile, &mut exploration.assumed);
+
+        if let Statement::Instruction { opcode, operands } = &line.statement {
+            if let Some(position) = opcode.write_parameter() {
+                if let Operand { mode: ParameterMode::Position, value } = operands[position] {
+                    if value >= 0 {
+                        exploration.writes.insert(value as usize);
+                    }
+                }
+            }
+        }
+
+        let branches = flows.len() > 1 || flows.iter().any(|flow| matches!(flow, Flow::Jump(_)));
+        for flow in flows.iter().rev() {
+            match flow {
+                Flow::Next(next) => {
+                    if branches {
+                        exploration.leaders.insert(*next);
+                    }
+                    pending.push(*next);
+                },
+                Flow::Jump(target) => {
+                    exploration.leaders.insert(*target);
+                    pending.push(*target);
+                },
+                Flow::Computed => {},
+            }
+        }
+
+        exploration.lines.insert(address, (line, flows));
+    }
+
+    exploration
+}
+
+impl Cfg {
+    pub fn build(memory: &[i64]) -> Cfg {
+        let mut volatile = BTreeSet::new();
+        let exploration = loop {
+            let exploration = explore(memory, &volatile);
+            let written: Vec<usize> = exploration.assumed.intersection(&exploration.writes).copied().collect();
+
+            if written.is_empty() {
+                break exploration;
+            }
+            volatile.extend(written);
+        };
+
+        let mut blocks: Vec<Block> = vec![];
+        let mut edges = vec![];
+        let mut open = false;
+
+        for (address, (line, flows)) in exploration.lines.iter() {
+            let continues = open
+                && !exploration.leaders.contains(address)
+                && blocks.last().map(|block| block.end) == Some(*address);
+
+            if !continues {
+                blocks.push(Block {
+                    start: *address,
+                    end: *address,
+                    lines: vec![],
+                    computed_jump: false,
+                    self_modified: false,
+                });
+            }
+
+            let block = blocks.last_mut().expect("a block was just opened");
+            block.end = address + line.size();
+            block.lines.push(line.clone());
+
+            open = flows.as_slice() == [Flow::Next(block.end)];
+            let ends_block = !open
+                || exploration.leaders.contains(&block.end)
+                || !exploration.lines.contains_key(&block.end);
+
+            if ends_block {
+                for flow in flows {
+                    match flow {
+                        Flow::Next(to) => edges.push(Edge { from: block.start, to: *to, kind: EdgeKind::Fallthrough }),
+                        Flow::Jump(to) => edges.push(Edge { from: block.start, to: *to, kind: EdgeKind::Jump }),
+                        Flow::Computed => block.computed_jump = true,
+                    }
+                }
+            }
+        }
+
+        for block in blocks.iter_mut() {
+            block.self_modified = exploration.writes.range(block.start..block.end).next().is_some();
+        }
+
+        let mut unreachable: Vec<Range<usize>> = vec![];
+        let mut covered = 0;
+        for (address, (line, _)) in exploration.lines.iter() {
+            if *address > covered {
+                unreachable.push(covered..*address);
+            }
+            covered = covered.max(address + line.size());
+        }
+        if covered < memory.len() {
+            unreachable.push(covered..memory.len());
+        }
+
+        Cfg {
+            blocks,
+            edges,
+            unreachable,
+        }
+    }
+
+    /// Render the graph in Graphviz DOT. Self-modified blocks are drawn in
+    /// red and computed jumps lead to a shared `?` node.
+    pub fn to_dot(&self) -> String {
+        let mut dot = String::from("digraph cfg {\n    node [shape=box, fontname=\"monospace\"];\n");
+
+        for block in self.blocks.iter() {
+            let label: String = block.lines.iter()
+                .map(|line| format!("{}\\l", escape(&line.to_string().trim_start().replace("  ", " "))))
+                .collect();
+            let color = if block.self_modified { ", color=red" } else { "" };
+
+            writeln!(dot, "    b{} [label=\"{}\"{}];", block.start, label, color).unwrap();
+            if block.computed_jump {
+                writeln!(dot, "    b{} -> computed [style=dotted];", block.start).unwrap();
+            }
+        }
+
+        if self.blocks.iter().any(|block| block.computed_jump) {
+            dot.push_str("    computed [shape=diamond, label=\"?\"];\n");
+        }
+
+        for edge in self.edges.iter() {
+            let style = match edge.kind {
+                EdgeKind::Jump => "",
+                EdgeKind::Fallthrough => " [style=dashed]",
+            };
+            writeln!(dot, "    b{} -> b{}{};", edge.from, edge.to, style).unwrap();
+        }
+
+        if !self.unreachable.is_empty() {
+            let ranges: Vec<String> = self.unreachable.iter()
+                .map(|range| format!("{}..{}", range.start, range.end))
+                .collect();
+            writeln!(dot, "    unreachable [shape=note, label=\"unreachable: {}\"];", ranges.join(", ")).unwrap();
+        }
+
+        dot.push_str("}\n");
+        dot
+    }
+
+    /// Render the graph as a single JSON object.
+    pub fn to_json(&self) -> String {
+        let blocks: Vec<String> = self.blocks.iter()
+            .map(|block| {
+                let lines: Vec<String> = block.lines.iter()
+                    .map(|line| format!(
+                        r#"{{"address":{},"text":"{}"}}"#,
+                        line.address,
+                        escape(&line.statement.to_string()),
+                    ))
+                    .collect();
+
+                format!(
+                    r#"{{"start":{},"end":{},"instructions":[{}],"computed_jump":{},"self_modified":{}}}"#,
+                    block.start,
+                    block.end,
+                    lines.join(","),
+                    block.computed_jump,
+                    block.self_modified,
+                )
+            })
+            .collect();
+        let edges: Vec<String> = self.edges.iter()
+            .map(|edge| format!(r#"{{"from":{},"to":{},"kind":"{}"}}"#, edge.from, edge.to, edge.kind.name()))
+            .collect();
+        let unreachable: Vec<String> = self.unreachable.iter()
+            .map(|range| format!(r#"{{"start":{},"end":{}}}"#, range.start, range.end))
+            .collect();
+
+        format!(
+            r#"{{"blocks":[{}],"edges":[{}],"unreachable":[{}]}}"#,
+            blocks.join(","),
+            edges.join(","),
+            unreachable.join(","),
+        )
+    }
+}
+
+fn escape(text: &str) -> String {
+    text.replace('\\', "\\\\").replace('"', "\\\"")
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::asm::assemble;
+
+    fn starts(cfg: &Cfg) -> Vec<(usize, usize)> {
+        cfg.blocks.iter().map(|block| (block.start, block.end)).collect()
+    }
+
+    #[test]
+    fn it_splits_blocks_at_jumps_and_targets() {
+        let memory = assemble("
+                    in [value]
+                    jz [value], #zero
+                    out #1
+                    hlt
+            zero:   out #0
+                    hlt
+            value:  .data 0
+        ").unwrap();
+        let cfg = Cfg::build(&memory);
+
+        assert_eq!(starts(&cfg), vec![(0, 5), (5, 8), (8, 11)]);
+        assert_eq!(cfg.edges, vec![
+            Edge { from: 0, to: 8, kind: EdgeKind::Jump },
+            Edge { from: 0, to: 5, kind: EdgeKind::Fallthrough },
+        ]);
+        assert_eq!(cfg.unreachable, vec![11..12]);
+        assert!(cfg.blocks.iter().all(|block| !block.computed_jump));
+    }
+
+    #[test]
+    fn it_follows_constant_conditions_and_flags_computed_jumps() {
+        let memory = assemble("
+                    jz #0, #skip
+                    .data 42, 42
+            skip:   jnz #1, [target]
+            target: .data 0
+        ").unwrap();
+        let cfg = Cfg::build(&memory);
+
+        assert_eq!(starts(&cfg), vec![(0, 3), (5, 8)]);
+        assert_eq!(cfg.edges, vec![Edge { from: 0, to: 5, kind: EdgeKind::Jump }]);
+        assert!(cfg.blocks[1].computed_jump);
+        assert_eq!(cfg.unreachable, vec![3..5, 8..9]);
+    }
+
+    #[test]
+    fn it_distrusts_operands_the_program_writes() {
+        // The input lands in the condition of the jump.
+        let cfg = Cfg::build(&[3, 3, 1105, -1, 9, 1101, 0, 0, 12, 4, 12, 99, 1]);
+
+        assert_eq!(starts(&cfg), vec![(0, 5), (5, 9), (9, 12)]);
+        assert!(cfg.blocks[0].self_modified);
+        assert!(!cfg.blocks[1].self_modified);
+        assert_eq!(cfg.edges.len(), 3);
+        assert_eq!(cfg.unreachable, vec![12..13]);
+    }
+
+    #[test]
+    fn it_exports_dot_and_json() {
+        let cfg = Cfg::build(&[1105, 1, 4, 99, 1106, 0, 3]);
+
+        assert_eq!(cfg.to_dot(), [
+            "digraph cfg {\n",
+            "    node [shape=box, fontname=\"monospace\"];\n",
+            "    b0 [label=\"0 jnz #1, #4\\l\"];\n",
+            "    b3 [label=\"3 hlt\\l\"];\n",
+            "    b4 [label=\"4 jz #0, #3\\l\"];\n",
+            "    b0 -> b4;\n",
+            "    b4 -> b3;\n",
+            "}\n",
+        ].concat());
+        assert_eq!(cfg.to_json(), [
+            r#"{"blocks":["#,
+            r#"{"start":0,"end":3,"instructions":[{"address":0,"text":"jnz #1, #4"}],"computed_jump":false,"self_modified":false},"#,
+            r#"{"start":3,"end":4,"instructions":[{"address":3,"text":"hlt"}],"computed_jump":false,"self_modified":false},"#,
+            r#"{"start":4,"end":7,"instructions":[{"address":4,"text":"jz #0, #3"}],"computed_jump":false,"self_modified":false}"#,
+            r#"],"edges":[{"from":0,"to":4,"kind":"jump"},{"from":4,"to":3,"kind":"jump"}],"unreachable":[]}"#,
+        ].concat());
+    }
+}
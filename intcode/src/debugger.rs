@@ -0,0 +1,489 @@
+//! Interactive step debugger wrapped around a `Program`.
+
+use std::collections::BTreeSet;
+use std::fs;
+use std::io::{self, BufRead, Write};
+
+use crate::disasm;
+use crate::error::IntcodeError;
+use crate::history::History;
+use crate::program::{Program, Status};
+use crate::snapshot::Snapshot;
+
+const HELP: &str = "\
+step [N]            execute N instructions (default 1)
+back [N]            undo the last N instructions (default 1)
+rewind STEP         undo instructions until STEP instructions have executed
+continue            run until a breakpoint, watchpoint, input request or halt
+break ADDR          stop before executing the instruction at ADDR
+delete ADDR         remove the breakpoint at ADDR
+watch ADDR          stop after an instruction writes to ADDR
+unwatch ADDR        remove the watchpoint on ADDR
+inspect             print the instruction at the current index
+registers           print index, relative base, step count and pending input
+dump START [COUNT]  print COUNT memory cells from START (default 16)
+writer ADDR         print the last instruction that wrote to ADDR
+poke ADDR VALUE     overwrite the memory cell at ADDR
+input VALUE...      queue values for the input instruction
+save FILE           write a snapshot of the program and its output to FILE
+load FILE           replace the program with the snapshot stored in FILE
+quit                leave the debugger
+An empty line repeats the previous command.";
+
+#[derive(Debug, PartialEq, Clone)]
+pub enum Command {
+    Step(usize),
+    Back(usize),
+    Rewind(u64),
+    Continue,
+    Break(usize),
+    Delete(usize),
+    Watch(usize),
+    Unwatch(usize),
+    Inspect,
+    Registers,
+    Dump { start: usize, count: usize },
+    Writer(usize),
+    Poke { address: usize, value: i64 },
+    Input(Vec<i64>),
+    Save(String),
+    Load(String),
+    Help,
+    Quit,
+}
+
+fn argument<T: std::str::FromStr>(arguments: &[&str], position: usize) -> Result<T, String> {
+    let token = arguments.get(position).ok_or_else(|| "missing argument".to_string())?;
+
+    token.parse::<T>().map_err(|_| format!("invalid argument {:?}", token))
+}
+
+impl Command {
+    pub fn parse(line: &str) -> Result<Command, String> {
+        let words: Vec<&str> = line.split_whitespace().collect();
+        let (name, arguments) = match words.split_first() {
+            Some((name, arguments)) => (*name, arguments),
+            None => return Err("empty command".to_string()),
+        };
+
+        match name {
+            "s" | "step" => match arguments.is_empty() {
+                true => Ok(Command::Step(1)),
+                false => Ok(Command::Step(argument(arguments, 0)?)),
+            },
+            "back" => match arguments.is_empty() {
+                true => Ok(Command::Back(1)),
+                false => Ok(Command::Back(argument(arguments, 0)?)),
+            },
+            "rewind" => Ok(Command::Rewind(argument(arguments, 0)?)),
+            "c" | "continue" => Ok(Command::Continue),
+            "b" | "break" => Ok(Command::Break(argument(arguments, 0)?)),
+            "d" | "delete" => Ok(Command::Delete(argument(arguments, 0)?)),
+            "w" | "watch" => Ok(Command::Watch(argument(arguments, 0)?)),
+            "unwatch" => Ok(Command::Unwatch(argument(arguments, 0)?)),
+            "i" | "inspect" => Ok(Command::Inspect),
+            "r" | "registers" => Ok(Command::Registers),
+            "x" | "dump" => Ok(Command::Dump {
+                start: argument(arguments, 0)?,
+                count: match arguments.len() {
+                    1 => 16,
+                    _ => argument(arguments, 1)?,
+                },
+            }),
+            "writer" => Ok(Command::Writer(argument(arguments, 0)?)),
+            "p" | "poke" => Ok(Command::Poke {
+                address: argument(arguments, 0)?,
+                value: argument(arguments, 1)?,
+            }),
+            "in" | "input" if !arguments.is_empty() => Ok(Command::Input(
+                (0..arguments.len())
+                    .map(|position| argument(arguments, position))
+                    .collect::<Result<Vec<i64>, String>>()?,
+            )),
+            "in" | "input" => Err("missing argument".to_string()),
+            "save" => Ok(Command::Save(argument(arguments, 0)?)),
+            "load" => Ok(Command::Load(argument(arguments, 0)?)),
+            "h" | "help" => Ok(Command::Help),
+            "q" | "quit" => Ok(Command::Quit),
+            name => Err(format!("unknown command {:?}", name)),
+        }
+    }
+}
+
+/// Why execution stopped while stepping or continuing.
+#[derive(Debug, PartialEq, Clone)]
+pub enum Event {
+    Breakpoint(usize),
+    Watchpoint { address: usize, old: i64, new: i64 },
+    NeedsInput,
+    Halted,
+    Failed(IntcodeError),
+}
+
+pub struct Debugger {
+    history: History,
+    breakpoints: BTreeSet<usize>,
+    watchpoints: BTreeSet<usize>,
+    outputs: Vec<i64>,
+    last_command: Option<Command>,
+}
+
+impl Debugger {
+    pub fn new(program: Program) -> Debugger {
+        Debugger {
+            history: History::new(program),
+            breakpoints: BTreeSet::new(),
+            watchpoints: BTreeSet::new(),
+            outputs: vec![],
+            last_command: None,
+        }
+    }
+
+    pub fn program(&self) -> &Program {
+        self.history.program()
+    }
+
+    /// Number of instructions executed so far, less those stepped back over.
+    pub fn steps(&self) -> u64 {
+        self.history.steps()
+    }
+
+    /// Values the program has output so far.
+    pub fn outputs(&self) -> &[i64] {
+        &self.outputs
+    }
+
+    /// Execute one instruction, reporting output through `output`.
+    fn step(&mut self, output: &mut dyn Write) -> io::Result<Option<Event>> {
+        let execution = match self.history.step() {
+            Ok(execution) => execution,
+            Err(error) => return Ok(Some(Event::Failed(error))),
+        };
+
+        match execution.status {
+            Some(Status::NeedsInput) => return Ok(Some(Event::NeedsInput)),
+            Some(Status::Halted) => return Ok(Some(Event::Halted)),
+            Some(Status::Output(value)) => {
+                self.outputs.push(value);
+                writeln!(output, "output: {}", value)?
+            },
+            None => {},
+        }
+
+        match execution.writes().iter().find(|write| self.watchpoints.contains(&write.address)) {
+            Some(write) => Ok(Some(Event::Watchpoint {
+                address: write.address,
+                old: write.old,
+                new: write.new,
+            })),
+            None => Ok(None),
+        }
+    }
+
+    fn report(&self, event: &Event, output: &mut dyn Write) -> io::Result<()> {
+        match event {
+            Event::Breakpoint(address) => writeln!(output, "breakpoint at {}", address)?,
+            Event::Watchpoint { address, old, new } => {
+                writeln!(output, "watchpoint {}: {} -> {}", address, old, new)?
+            },
+            Event::NeedsInput => writeln!(output, "program needs input, queue some with `input VALUE`")?,
+            Event::Halted => writeln!(output, "program halted")?,
+            Event::Failed(error) => writeln!(output, "error: {}", error)?,
+        }
+
+        self.inspect(output)
+    }
+
+    fn inspect(&self, output: &mut dyn Write) -> io::Result<()> {
+        writeln!(output, "{}", disasm::decode(self.program().memory(), self.program().index))
+    }
+
+    /// Undo up to `count` instructions, forgetting the output they produced.
+    pub fn step_back(&mut self, count: usize) -> usize {
+        let mut undone = 0;
+
+        while undone < count {
+            match self.history.step_back() {
+                Some(entry) => {
+                    if entry.execution.output.is_some() {
+                        self.outputs.pop();
+                    }
+                    undone += 1;
+                },
+                None => break,
+            }
+        }
+
+        undone
+    }
+
+    /// Run `count` instructions, stopping early on any event.
+    pub fn step_over(&mut self, count: usize, output: &mut dyn Write) -> io::Result<Option<Event>> {
+        for _ in 0..count {
+            if let Some(event) = self.step(output)? {
+                return Ok(Some(event));
+            }
+        }
+
+        Ok(None)
+    }
+
+    /// Run until an event stops execution. Always executes at least one
+    /// instruction, so continuing from a breakpoint moves past it.
+    pub fn resume(&mut self, output: &mut dyn Write) -> io::Result<Event> {
+        loop {
+            if let Some(event) = self.step(output)? {
+                return Ok(event);
+            }
+
+            if self.breakpoints.contains(&self.program().index) {
+                return Ok(Event::Breakpoint(self.program().index));
+            }
+        }
+    }
+
+    /// Execute a single command. Returns `false` once the user asked to quit.
+    pub fn execute(&mut self, command: Command, output: &mut dyn Write) -> io::Result<bool> {
+        match command {
+            Command::Step(count) => match self.step_over(count, output)? {
+                Some(event) => self.report(&event, output)?,
+                None => self.inspect(output)?,
+            },
+            Command::Back(count) => {
+                if self.step_back(count) < count {
+                    writeln!(output, "reached the start of the recorded history")?;
+                }
+                self.inspect(output)?;
+            },
+            Command::Rewind(step) => {
+                let count = self.steps().saturating_sub(step) as usize;
+                self.step_back(count);
+                self.inspect(output)?;
+            },
+            Command::Continue => {
+                let event = self.resume(output)?;
+                self.report(&event, output)?;
+            },
+            Command::Break(address) => {
+                self.breakpoints.insert(address);
+            },
+            Command::Delete(address) => {
+                self.breakpoints.remove(&address);
+            },
+            Command::Watch(address) => {
+                self.watchpoints.insert(address);
+            },
+            Command::Unwatch(address) => {
+                self.watchpoints.remove(&address);
+            },
+            Command::Inspect => self.inspect(output)?,
+            Command::Registers => {
+                writeln!(output, "index: {}", self.program().index)?;
+                writeln!(output, "relative base: {}", self.program().relative_base)?;
+                writeln!(output, "steps: {}", self.steps())?;
+                writeln!(output, "input: {:?}", self.program().pending_input())?;
+            },
+            Command::Dump { start, count } => {
+                let end = start.saturating_add(count);
+
+                for row in (start..end).step_by(8) {
+                    let values: Vec<String> = (row..end.min(row + 8))
+                        .map(|address| self.program()[address].to_string())
+                        .collect();
+                    writeln!(output, "{:>5}: {}", row, values.join(" "))?;
+                }
+            },
+            Command::Writer(address) => match self.history.last_write(address) {
+                Some(entry) => {
+                    let write = entry.execution.writes().iter()
+                        .rfind(|write| write.address == address)
+                        .expect("entries found by address have a write");
+                    writeln!(
+                        output,
+                        "step {}: word {} at {} wrote {} -> {}",
+                        entry.step,
+                        entry.execution.word,
+                        entry.execution.address,
+                        write.old,
+                        write.new,
+                    )?;
+                },
+                None => writeln!(output, "no recorded instruction wrote to {}", address)?,
+            },
+            Command::Poke { address, value } => {
+                if let Err(error) = self.history.program_mut().write(address, value) {
+                    writeln!(output, "error: {}", error)?;
+                }
+            },
+            Command::Input(values) => {
+                for value in values {
+                    self.history.program_mut().push_input(value);
+                }
+            },
+            Command::Save(path) => {
+                let saved = Snapshot::capture(self.program(), &self.outputs)
+                    .map_err(|error| error.to_string())
+                    .and_then(|snapshot| fs::write(&path, snapshot.to_string()).map_err(|error| error.to_string()));
+
+                if let Err(error) = saved {
+                    writeln!(output, "error: {}", error)?;
+                }
+            },
+            Command::Load(path) => {
+                let snapshot = fs::read_to_string(&path)
+                    .map_err(|error| error.to_string())
+                    .and_then(|text| Snapshot::parse(&text).map_err(|error| error.to_string()));
+
+                match snapshot {
+                    Ok(snapshot) => {
+                        self.history = History::new(snapshot.restore());
+                        self.outputs = snapshot.output;
+                        self.inspect(output)?;
+                    },
+                    Err(error) => writeln!(output, "error: {}", error)?,
+                }
+            },
+            Command::Help => writeln!(output, "{}", HELP)?,
+            Command::Quit => return Ok(false),
+        }
+
+        Ok(true)
+    }
+
+    /// Read commands from `input` until it ends or the user quits.
+    pub fn repl(&mut self, input: impl BufRead, mut output: impl Write) -> io::Result<()> {
+        let mut lines = input.lines();
+
+        loop {
+            write!(output, "(icdb) ")?;
+            output.flush()?;
+
+            let line = match lines.next() {
+                Some(line) => line?,
+                None => return Ok(()),
+            };
+
+            let command = match (line.trim().is_empty(), &self.last_command) {
+                (true, Some(command)) => Ok(command.clone()),
+                _ => Command::parse(&line),
+            };
+
+            match command {
+                Ok(command) => {
+                    self.last_command = Some(command.clone());
+
+                    if !self.execute(command, &mut output)? {
+                        return Ok(());
+                    }
+                },
+                Err(message) => writeln!(output, "error: {}", message)?,
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::program::parse_program_into_instructions;
+
+    fn debugger(source: &str) -> Debugger {
+        Debugger::new(Program::from(parse_program_into_instructions(source).unwrap()))
+    }
+
+    #[test]
+    fn it_parses_commands() {
+        assert_eq!(Command::parse("step"), Ok(Command::Step(1)));
+        assert_eq!(Command::parse("s 10"), Ok(Command::Step(10)));
+        assert_eq!(Command::parse("x 4"), Ok(Command::Dump { start: 4, count: 16 }));
+        assert_eq!(Command::parse("poke 3 -7"), Ok(Command::Poke { address: 3, value: -7 }));
+        assert_eq!(Command::parse("input 1 2"), Ok(Command::Input(vec![1, 2])));
+        assert_eq!(Command::parse("back"), Ok(Command::Back(1)));
+        assert_eq!(Command::parse("rewind 3"), Ok(Command::Rewind(3)));
+        assert_eq!(Command::parse("save run.snapshot"), Ok(Command::Save("run.snapshot".to_string())));
+        assert_eq!(Command::parse("break x"), Err("invalid argument \"x\"".to_string()));
+        assert_eq!(Command::parse("jump"), Err("unknown command \"jump\"".to_string()));
+    }
+
+    #[test]
+    fn it_stops_at_breakpoints_and_watchpoints() {
+        let mut debugger = debugger("1101,1,2,9,1101,3,4,10,99,0,0");
+        let mut output = vec![];
+
+        debugger.execute(Command::Break(8), &mut output).unwrap();
+        debugger.execute(Command::Watch(10), &mut output).unwrap();
+
+        assert_eq!(debugger.resume(&mut output).unwrap(), Event::Watchpoint { address: 10, old: 0, new: 7 });
+        assert_eq!(debugger.program().index, 8);
+        assert_eq!(debugger.steps(), 2);
+
+        debugger.execute(Command::Unwatch(10), &mut output).unwrap();
+        debugger.execute(Command::Poke { address: 8, value: 1101 }, &mut output).unwrap();
+        debugger.execute(Command::Poke { address: 12, value: 99 }, &mut output).unwrap();
+        assert_eq!(debugger.resume(&mut output).unwrap(), Event::Halted);
+    }
+
+    #[test]
+    fn it_runs_a_session_from_text() {
+        let mut debugger = debugger("3,9,8,9,10,9,4,9,99,-1,8");
+        let mut output = vec![];
+
+        debugger.repl("c\ninput 8\ns\n\nx 9 2\nc\nq\n".as_bytes(), &mut output).unwrap();
+
+        assert_eq!(String::from_utf8(output).unwrap(), [
+            "(icdb) program needs input, queue some with `input VALUE`\n",
+            "    0  in [9]\n",
+            "(icdb) (icdb)     2  eq [9], [10], [9]\n",
+            "(icdb)     6  out [9]\n",
+            "(icdb)     9: 1 8\n",
+            "(icdb) output: 1\n",
+            "program halted\n",
+            "    8  hlt\n",
+            "(icdb) ",
+        ].concat());
+    }
+
+    #[test]
+    fn it_saves_and_loads_snapshots() {
+        let path = std::env::temp_dir().join(format!("icdb-{}.snapshot", std::process::id()));
+        let path = path.to_str().unwrap().to_string();
+        let mut debugger = debugger("104,7,104,8,99");
+        let mut output = vec![];
+
+        debugger.execute(Command::Step(1), &mut output).unwrap();
+        debugger.execute(Command::Save(path.clone()), &mut output).unwrap();
+        assert_eq!(debugger.resume(&mut output).unwrap(), Event::Halted);
+        assert_eq!(debugger.outputs(), &[7, 8]);
+
+        debugger.execute(Command::Load(path.clone()), &mut output).unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        assert_eq!(debugger.program().index, 2);
+        assert_eq!(debugger.outputs(), &[7]);
+        assert_eq!(debugger.resume(&mut output).unwrap(), Event::Halted);
+        assert_eq!(debugger.outputs(), &[7, 8]);
+    }
+
+    #[test]
+    fn it_steps_backwards_through_a_session() {
+        let mut debugger = debugger("1101,1,2,9,1102,3,4,9,104,5,99");
+        let mut output = vec![];
+
+        debugger.repl("c\nwriter 9\nback\nrewind 1\nx 9 1\nback 5\nq\n".as_bytes(), &mut output).unwrap();
+
+        assert_eq!(String::from_utf8(output).unwrap(), [
+            "(icdb) output: 12\n",
+            "program halted\n",
+            "   10  hlt\n",
+            "(icdb) step 1: word 1102 at 4 wrote 3 -> 12\n",
+            "(icdb)     8  out #12\n",
+            "(icdb)     4  mul #3, #4, [9]\n",
+            "(icdb)     9: 3\n",
+            "(icdb) reached the start of the recorded history\n",
+            "    0  add #1, #2, [9]\n",
+            "(icdb) ",
+        ].concat());
+        assert!(debugger.outputs().is_empty());
+        assert_eq!(debugger.program()[9], 5);
+    }
+}
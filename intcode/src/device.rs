@@ -0,0 +1,315 @@
+//! Devices mapped into program memory.
+//!
+//! `Program::map_device` places a device at a range of addresses. Reads and
+//! writes to those addresses, including the ones instructions make, go to
+//! the device instead of memory. Devices are shared between the program,
+//! its clones and whoever else holds on to them, so a harness can inspect a
+//! framebuffer after the program drew into it:
+//!
+//! ```
+//! use std::sync::Arc;
+//! use intcode::device::Framebuffer;
+//! use intcode::{Program, Status};
+//!
+//! let screen = Arc::new(Framebuffer::new(3, 2));
+//! let mut program = Program::from(vec![1101, 0, 1, 100, 1101, 0, 1, 105, 99]);
+//! program.map_device(100, screen.clone()).unwrap();
+//!
+//! assert_eq!(program.run(), Ok(Status::Halted));
+//! assert_eq!(screen.render(), "#..\n..#\n");
+//! ```
+
+use std::collections::VecDeque;
+use std::io::Write;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Mutex;
+
+use crate::ascii;
+use crate::error::IntcodeError;
+
+pub trait Device: Send + Sync {
+    /// Number of consecutive addresses the device occupies.
+    fn len(&self) -> usize;
+
+    fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Read the cell `offset` cells into the device.
+    fn read(&self, offset: usize) -> Result<i64, IntcodeError>;
+
+    /// Write the cell `offset` cells into the device.
+    fn write(&self, offset: usize, value: i64) -> Result<(), IntcodeError>;
+
+    /// Called after every instruction the program executes.
+    fn tick(&self) {}
+}
+
+/// Counts the instructions executed since it was mapped. Writing sets the
+/// count.
+#[derive(Debug, Default)]
+pub struct CycleCounter {
+    cycles: AtomicU64,
+}
+
+impl CycleCounter {
+    pub fn new() -> CycleCounter {
+        CycleCounter::default()
+    }
+
+    pub fn cycles(&self) -> u64 {
+        self.cycles.load(Ordering::Relaxed)
+    }
+}
+
+impl Device for CycleCounter {
+    fn len(&self) -> usize {
+        1
+    }
+
+    fn read(&self, _: usize) -> Result<i64, IntcodeError> {
+        Ok(self.cycles() as i64)
+    }
+
+    fn write(&self, _: usize, value: i64) -> Result<(), IntcodeError> {
+        self.cycles.store(value as u64, Ordering::Relaxed);
+
+        Ok(())
+    }
+
+    fn tick(&self) {
+        self.cycles.fetch_add(1, Ordering::Relaxed);
+    }
+}
+
+/// Pseudo-random non-negative numbers from a xorshift generator, a new one
+/// on every read. Writing reseeds it, so runs can be reproduced.
+#[derive(Debug)]
+pub struct RandomSource {
+    state: Mutex<u64>,
+}
+
+impl RandomSource {
+    pub fn new(seed: u64) -> RandomSource {
+        RandomSource { state: Mutex::new(seed.max(1)) }
+    }
+}
+
+impl Device for RandomSource {
+    fn len(&self) -> usize {
+        1
+    }
+
+    fn read(&self, _: usize) -> Result<i64, IntcodeError> {
+        let mut state = self.state.lock().unwrap();
+        *state ^= *state << 13;
+        *state ^= *state >> 7;
+        *state ^= *state << 17;
+
+        Ok((*state >> 1) as i64)
+    }
+
+    fn write(&self, _: usize, value: i64) -> Result<(), IntcodeError> {
+        *self.state.lock().unwrap() = (value as u64).max(1);
+
+        Ok(())
+    }
+}
+
+/// A grid of pixels, stored row by row.
+#[derive(Debug)]
+pub struct Framebuffer {
+    width: usize,
+    height: usize,
+    pixels: Mutex<Vec<i64>>,
+}
+
+impl Framebuffer {
+    pub fn new(width: usize, height: usize) -> Framebuffer {
+        Framebuffer {
+            width,
+            height,
+            pixels: Mutex::new(vec![0; width * height]),
+        }
+    }
+
+    pub fn pixel(&self, x: usize, y: usize) -> i64 {
+        self.pixels.lock().unwrap()[y * self.width + x]
+    }
+
+    /// One line per row, `#` for pixels that are set and `.` for the rest.
+    pub fn render(&self) -> String {
+        let pixels = self.pixels.lock().unwrap();
+
+        pixels.chunks(self.width.max(1))
+            .take(self.height)
+            .map(|row| {
+                let mut line: String = row.iter().map(|pixel| if *pixel == 0 { '.' } else { '#' }).collect();
+                line.push('\n');
+                line
+            })
+            .collect()
+    }
+}
+
+impl Device for Framebuffer {
+    fn len(&self) -> usize {
+        self.width * self.height
+    }
+
+    fn read(&self, offset: usize) -> Result<i64, IntcodeError> {
+        Ok(self.pixels.lock().unwrap()[offset])
+    }
+
+    fn write(&self, offset: usize, value: i64) -> Result<(), IntcodeError> {
+        self.pixels.lock().unwrap()[offset] = value;
+
+        Ok(())
+    }
+}
+
+/// A single cell talking ASCII. Writing prints the value the way
+/// `ascii::Console` does; reading takes the next queued character, or -1
+/// when there is none.
+#[derive(Debug)]
+pub struct ConsolePort<W> {
+    output: Mutex<W>,
+    input: Mutex<VecDeque<i64>>,
+}
+
+impl<W: Write + Send> ConsolePort<W> {
+    pub fn new(output: W) -> ConsolePort<W> {
+        ConsolePort {
+            output: Mutex::new(output),
+            input: Mutex::new(VecDeque::new()),
+        }
+    }
+
+    /// Queue the characters of `text` for reading.
+    pub fn push_input(&self, text: &str) -> Result<(), IntcodeError> {
+        self.input.lock().unwrap().extend(ascii::encode(text)?);
+
+        Ok(())
+    }
+
+    pub fn into_output(self) -> W {
+        self.output.into_inner().unwrap()
+    }
+}
+
+impl<W: Write + Send> Device for ConsolePort<W> {
+    fn len(&self) -> usize {
+        1
+    }
+
+    fn read(&self, _: usize) -> Result<i64, IntcodeError> {
+        Ok(self.input.lock().unwrap().pop_front().unwrap_or(-1))
+    }
+
+    fn write(&self, _: usize, value: i64) -> Result<(), IntcodeError> {
+        self.output.lock().unwrap()
+            .write_all(ascii::decode(&[value]).as_bytes())
+            .map_err(|error| IntcodeError::Device { message: error.to_string() })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::history::History;
+    use crate::program::{Program, Status};
+    use std::sync::Arc;
+
+    #[test]
+    fn it_counts_cycles() {
+        // Reads the counter after two instructions and outputs it.
+        let counter = Arc::new(CycleCounter::new());
+        let mut program = Program::from(vec![1101, 0, 0, 11, 1101, 0, 0, 11, 4, 50, 99, 0]);
+        program.map_device(50, counter.clone()).unwrap();
+
+        assert_eq!(program.run(), Ok(Status::Output(2)));
+        assert_eq!(program.run(), Ok(Status::Halted));
+        assert_eq!(counter.cycles(), 4);
+    }
+
+    #[test]
+    fn it_reproduces_random_numbers_after_reseeding() {
+        let random = RandomSource::new(7);
+        let first = (0..3).map(|_| random.read(0).unwrap()).collect::<Vec<_>>();
+        random.write(0, 7).unwrap();
+
+        assert_eq!((0..3).map(|_| random.read(0).unwrap()).collect::<Vec<_>>(), first);
+        assert!(first.iter().all(|value| *value >= 0));
+        assert_ne!(first[0], first[1]);
+    }
+
+    #[test]
+    fn it_talks_through_the_console_port() {
+        // Copies characters from the port back to it until it reads -1.
+        let console = Arc::new(ConsolePort::new(vec![]));
+        console.push_input("hi").unwrap();
+        let mut program = Program::from(vec![1001, 30, 0, 32, 1008, 32, -1, 31, 1005, 31, 18, 1001, 32, 0, 30, 1105, 1, 0, 99]);
+        program.map_device(30, console.clone()).unwrap();
+
+        assert_eq!(program.run(), Ok(Status::Halted));
+        drop(program);
+        assert_eq!(Arc::try_unwrap(console).unwrap().into_output(), b"hi".to_vec());
+    }
+
+    #[test]
+    fn it_keeps_memory_around_devices_intact() {
+        let screen = Arc::new(Framebuffer::new(2, 1));
+        let mut program = Program::from(vec![99, 5, 6, 7]);
+        program.map_device(1, screen.clone()).unwrap();
+
+        program.write(2, 3).unwrap();
+        assert_eq!(program.value(1), Ok(0));
+        assert_eq!(program.value(2), Ok(3));
+        assert_eq!(program.value(3), Ok(7));
+        assert_eq!(program.memory(), &[99, 5, 6, 7]);
+        assert_eq!(screen.pixel(1, 0), 3);
+    }
+
+    #[test]
+    fn it_reads_instructions_from_devices_once() {
+        // The port holds `c`, which is 99 and halts.
+        let console = Arc::new(ConsolePort::new(vec![]));
+        console.push_input("c").unwrap();
+        let mut program = Program::from(vec![]);
+        program.map_device(0, console.clone()).unwrap();
+
+        assert_eq!(program.step(), Ok(Some(Status::Halted)));
+        assert_eq!(console.read(0), Ok(-1));
+    }
+
+    #[test]
+    fn it_leaves_devices_alone_when_stepping_back() {
+        let screen = Arc::new(Framebuffer::new(1, 1));
+        let mut program = Program::from(vec![1101, 0, 1, 5, 99, 42]);
+        program.map_device(5, screen.clone()).unwrap();
+        let mut history = History::new(program);
+
+        history.step().unwrap();
+        history.step_back();
+
+        assert_eq!(screen.pixel(0, 0), 1);
+        assert_eq!(history.program().memory(), &[1101, 0, 1, 5, 99, 42]);
+        assert_eq!(history.program().index, 0);
+    }
+
+    #[test]
+    fn it_refuses_overlapping_devices() {
+        let mut program = Program::from(vec![99]);
+        program.map_device(10, Arc::new(Framebuffer::new(4, 1))).unwrap();
+
+        assert_eq!(
+            program.map_device(13, Arc::new(CycleCounter::new())),
+            Err(IntcodeError::InvalidDeviceMapping { start: 13, len: 1 }),
+        );
+        assert_eq!(
+            program.map_device(usize::MAX, Arc::new(Framebuffer::new(2, 1))),
+            Err(IntcodeError::InvalidDeviceMapping { start: usize::MAX, len: 2 }),
+        );
+        assert_eq!(program.map_device(14, Arc::new(CycleCounter::new())), Ok(()));
+    }
+}
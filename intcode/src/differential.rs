@@ -0,0 +1,244 @@
+//! Differential tests running random programs on `Program` and on a
+//! deliberately naive reference interpreter written straight from the
+//! puzzle descriptions.
+
+use std::collections::VecDeque;
+
+use proptest::prelude::*;
+
+use crate::program::{Program, Status};
+
+/// Instructions either machine executes before giving up on a program.
+const BUDGET: usize = 500;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Outcome {
+    Halted,
+    NeedsInput,
+    Failed,
+    OutOfSteps,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+struct State {
+    outcome: Outcome,
+    memory: Vec<i64>,
+    outputs: Vec<i64>,
+    /// Registers, compared unless the run failed.
+    registers: Option<(usize, i64)>,
+}
+
+struct Reference {
+    memory: Vec<i64>,
+    ip: i64,
+    base: i64,
+    input: VecDeque<i64>,
+    outputs: Vec<i64>,
+}
+
+impl Reference {
+    fn read(&self, address: i64) -> Result<i64, ()> {
+        if address < 0 {
+            return Err(());
+        }
+
+        Ok(self.memory.get(address as usize).copied().unwrap_or(0))
+    }
+
+    fn store(&mut self, address: i64, value: i64) -> Result<(), ()> {
+        if address < 0 {
+            return Err(());
+        }
+
+        let address = address as usize;
+        if address >= self.memory.len() {
+            self.memory.resize(address + 1, 0);
+        }
+        self.memory[address] = value;
+
+        Ok(())
+    }
+
+    /// Execute one instruction, returning `Some` when the machine stops.
+    fn step(&mut self) -> Result<Option<Outcome>, ()> {
+        let word = self.read(self.ip)?;
+        if !(0..=99999).contains(&word) {
+            return Err(());
+        }
+
+        let opcode = word % 100;
+        let modes = [word / 100 % 10, word / 1000 % 10, word / 10000 % 10];
+        let arity = match opcode {
+            1 | 2 | 7 | 8 => 3,
+            5 | 6 => 2,
+            3 | 4 | 9 => 1,
+            99 => 0,
+            _ => return Err(()),
+        };
+        if modes[..arity].iter().any(|mode| *mode > 2) {
+            return Err(());
+        }
+
+        let mut values = [0; 3];
+        for (position, value) in values.iter_mut().enumerate().take(arity) {
+            let raw = self.read(self.ip + 1 + position as i64)?;
+            let writes = match opcode {
+                1 | 2 | 7 | 8 => position == 2,
+                3 => true,
+                _ => false,
+            };
+
+            // Destinations resolve to addresses before anything executes, so
+            // a negative one fails even an input instruction that would wait.
+            *value = match (modes[position], writes) {
+                (0, true) if raw >= 0 => raw,
+                (2, true) => match self.base.checked_add(raw) {
+                    Some(address) if address >= 0 => address,
+                    _ => return Err(()),
+                },
+                (_, true) => return Err(()),
+                (0, false) => self.read(raw)?,
+                (1, false) => raw,
+                _ => self.read(self.base.checked_add(raw).ok_or(())?)?,
+            };
+        }
+
+        let next = self.ip + 1 + arity as i64;
+        match opcode {
+            1 => self.store(values[2], values[0].checked_add(values[1]).ok_or(())?)?,
+            2 => self.store(values[2], values[0].checked_mul(values[1]).ok_or(())?)?,
+            3 => match self.input.pop_front() {
+                Some(value) => self.store(values[0], value)?,
+                None => return Ok(Some(Outcome::NeedsInput)),
+            },
+            4 => self.outputs.push(values[0]),
+            5 | 6 if (values[0] != 0) == (opcode == 5) => {
+                if values[1] < 0 {
+                    return Err(());
+                }
+                self.ip = values[1];
+                return Ok(None);
+            },
+            5 | 6 => {},
+            7 => self.store(values[2], (values[0] < values[1]) as i64)?,
+            8 => self.store(values[2], (values[0] == values[1]) as i64)?,
+            9 => self.base = self.base.checked_add(values[0]).ok_or(())?,
+            _ => return Ok(Some(Outcome::Halted)),
+        }
+
+        self.ip = next;
+        Ok(None)
+    }
+
+    fn run(memory: &[i64], input: &[i64]) -> State {
+        let mut machine = Reference {
+            memory: memory.to_vec(),
+            ip: 0,
+            base: 0,
+            input: input.iter().copied().collect(),
+            outputs: vec![],
+        };
+
+        let mut outcome = Outcome::OutOfSteps;
+        for _ in 0..=BUDGET {
+            match machine.step() {
+                Ok(None) => continue,
+                Ok(Some(stopped)) => outcome = stopped,
+                Err(()) => outcome = Outcome::Failed,
+            }
+            break;
+        }
+
+        State {
+            registers: match outcome {
+                Outcome::Failed => None,
+                _ => Some((machine.ip as usize, machine.base)),
+            },
+            outcome,
+            memory: machine.memory,
+            outputs: machine.outputs,
+        }
+    }
+}
+
+/// Drive `Program::execute` the way the dec05 runner does, within the budget.
+fn run_vm(memory: &[i64], input: &[i64]) -> State {
+    let mut program = Program::from(memory.to_vec());
+    for value in input {
+        program.push_input(*value);
+    }
+
+    let mut outputs = vec![];
+    let mut outcome = Outcome::OutOfSteps;
+    for _ in 0..=BUDGET {
+        match program.execute().map(|execution| execution.status) {
+            Ok(None) => continue,
+            Ok(Some(Status::Output(value))) => {
+                outputs.push(value);
+                continue;
+            },
+            Ok(Some(Status::NeedsInput)) => outcome = Outcome::NeedsInput,
+            Ok(Some(Status::Halted)) => outcome = Outcome::Halted,
+            Err(_) => outcome = Outcome::Failed,
+        }
+        break;
+    }
+
+    State {
+        registers: match outcome {
+            Outcome::Failed => None,
+            _ => Some((program.index, program.relative_base)),
+        },
+        outcome,
+        memory: program.memory().to_vec(),
+        outputs,
+    }
+}
+
+fn arity(opcode: i64) -> usize {
+    match opcode {
+        1 | 2 | 7 | 8 => 3,
+        5 | 6 => 2,
+        3 | 4 | 9 => 1,
+        _ => 0,
+    }
+}
+
+/// One instruction with random modes, including the odd invalid one, and
+/// operands small enough to mostly land inside the program.
+fn instruction() -> impl Strategy<Value = Vec<i64>> {
+    let opcodes = prop::sample::select(vec![1, 2, 3, 4, 5, 6, 7, 8, 9, 99]);
+    let modes = prop::collection::vec(prop_oneof![10 => 0i64..=2, 1 => Just(3i64)], 3);
+    let operands = prop::collection::vec(-3i64..40, 3);
+
+    (opcodes, modes, operands).prop_map(|(opcode, modes, operands)| {
+        let word = modes[2] * 10000 + modes[1] * 1000 + modes[0] * 100 + opcode;
+
+        std::iter::once(word).chain(operands[..arity(opcode)].iter().copied()).collect()
+    })
+}
+
+fn program() -> impl Strategy<Value = (Vec<i64>, Vec<i64>)> {
+    (
+        prop::collection::vec(instruction(), 1..16).prop_map(|instructions| instructions.concat()),
+        prop::collection::vec(-5i64..50, 0..4),
+    )
+}
+
+proptest! {
+    #[test]
+    fn it_matches_the_reference_interpreter((memory, input) in program()) {
+        let expected = Reference::run(&memory, &input);
+        prop_assert_eq!(run_vm(&memory, &input), expected.clone());
+
+        // Programs that halt must also agree when run the way the puzzles do.
+        if expected.outcome == Outcome::Halted {
+            let mut program = Program::from(memory);
+            let mut outputs = vec![];
+            program.run_with(&mut input.iter().copied().collect::<VecDeque<i64>>(), &mut outputs).unwrap();
+
+            prop_assert_eq!(program.memory(), &expected.memory[..]);
+            prop_assert_eq!(outputs, expected.outputs);
+        }
+    }
+}
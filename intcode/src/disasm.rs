@@ -0,0 +1,147 @@
+use std::fmt;
+
+use crate::instruction::{Instruction, Opcode, ParameterMode};
+
+/// A decoded parameter: the raw word following the opcode and how it is read.
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub struct Operand {
+    pub mode: ParameterMode,
+    pub value: i64,
+}
+
+impl fmt::Display for Operand {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.mode {
+            ParameterMode::Position => write!(f, "[{}]", self.value),
+            ParameterMode::Immediate => write!(f, "#{}", self.value),
+            ParameterMode::Relative if self.value < 0 => write!(f, "[rb{}]", self.value),
+            ParameterMode::Relative => write!(f, "[rb+{}]", self.value),
+        }
+    }
+}
+
+#[derive(Debug, PartialEq, Clone)]
+pub enum Statement {
+    Instruction { opcode: Opcode, operands: Vec<Operand> },
+    /// A word that does not decode into an instruction, or whose parameters
+    /// run past the end of the program.
+    Data(i64),
+}
+
+/// One line of a listing, starting at `address`.
+#[derive(Debug, PartialEq, Clone)]
+pub struct Line {
+    pub address: usize,
+    pub statement: Statement,
+}
+
+impl Line {
+    /// Number of memory cells covered by the line.
+    pub fn size(&self) -> usize {
+        match &self.statement {
+            Statement::Instruction { operands, .. } => 1 + operands.len(),
+            Statement::Data(_) => 1,
+        }
+    }
+}
+
+impl fmt::Display for Statement {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Statement::Instruction { opcode, operands } => {
+                write!(f, "{}", opcode.mnemonic())?;
+
+                for (position, operand) in operands.iter().enumerate() {
+                    let separator = if position == 0 { " " } else { ", " };
+                    write!(f, "{}{}", separator, operand)?;
+                }
+
+                Ok(())
+            },
+            Statement::Data(value) => write!(f, ".data {}", value),
+        }
+    }
+}
+
+impl fmt::Display for Line {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{:>5}  {}", self.address, self.statement)
+    }
+}
+
+/// Decode the statement starting at `address`. Addresses past the end of
+/// `memory` decode as zero-valued data.
+pub fn decode(memory: &[i64], address: usize) -> Line {
+    let word = memory.get(address).copied().unwrap_or(0);
+    let statement = match Instruction::parse(word) {
+        Ok(instruction) if address + instruction.size() <= memory.len() => {
+            let operands = instruction.parameters().iter()
+                .map(|parameter| Operand {
+                    mode: parameter.mode,
+                    value: memory[address + 1 + parameter.position],
+                })
+                .collect();
+
+            Statement::Instruction {
+                opcode: instruction.opcode,
+                operands,
+            }
+        },
+        _ => Statement::Data(word),
+    };
+
+    Line { address, statement }
+}
+
+/// Walk the program linearly from address zero, decoding every statement.
+pub fn disassemble(memory: &[i64]) -> Vec<Line> {
+    let mut lines = vec![];
+    let mut address = 0;
+
+    while address < memory.len() {
+        let line = decode(memory, address);
+        address += line.size();
+        lines.push(line);
+    }
+
+    lines
+}
+
+/// Render a complete listing, one statement per line.
+pub fn listing(memory: &[i64]) -> String {
+    disassemble(memory).iter()
+        .map(|line| format!("{}\n", line))
+        .collect()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn it_disassembles_instructions_and_data() {
+        let memory = vec![1002, 4, 3, 4, 33, 104, -1, 21101, 2, 3, -4, 1105];
+
+        assert_eq!(listing(&memory), [
+            "    0  mul [4], #3, [4]\n",
+            "    4  .data 33\n",
+            "    5  out #-1\n",
+            "    7  add #2, #3, [rb-4]\n",
+            "   11  .data 1105\n",
+        ].concat());
+    }
+
+    #[test]
+    fn it_decodes_operands_with_modes() {
+        let line = decode(&[99, 209, 7], 1);
+
+        assert_eq!(line, Line {
+            address: 1,
+            statement: Statement::Instruction {
+                opcode: Opcode::AdjustRelativeBase,
+                operands: vec![Operand { mode: ParameterMode::Relative, value: 7 }],
+            },
+        });
+        assert_eq!(line.to_string(), "    1  arb [rb+7]");
+    }
+}
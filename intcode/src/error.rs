@@ -0,0 +1,107 @@
+use std::fmt;
+use std::time::Duration;
+
+/// Reasons a single opcode word cannot be decoded into an `Instruction`.
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum DecodeError {
+    Overflow(i64),
+    UnknownOpcode(u32),
+    InvalidParameterMode(u32),
+}
+
+impl fmt::Display for DecodeError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::Overflow(word) => write!(f, "opcode word {} is out of range", word),
+            Self::UnknownOpcode(opcode) => write!(f, "unknown opcode {}", opcode),
+            Self::InvalidParameterMode(mode) => write!(f, "unsupported parameter mode {}", mode),
+        }
+    }
+}
+
+impl std::error::Error for DecodeError {}
+
+/// Everything that can go wrong while loading or executing an intcode program.
+///
+/// Errors raised during execution leave `Program::index` pointing at the
+/// instruction that failed.
+#[derive(Debug, PartialEq, Clone)]
+pub enum IntcodeError {
+    UnknownOpcode { address: usize, opcode: i64 },
+    InvalidParameterMode { address: usize, mode: u32 },
+    OutOfBoundsWrite { address: usize },
+    /// The instruction at `address` wrote more cells than `handler::MAX_WRITES`.
+    TooManyWrites { address: usize },
+    NegativeAddress { value: i64 },
+    ArithmeticOverflow { address: usize },
+    Parse { column: usize, token: String },
+    InputUnavailable,
+    OutputUnavailable,
+    MissingOutput,
+    Device { message: String },
+    /// A device of `len` cells at `start` overlaps another device or runs
+    /// past the last address.
+    InvalidDeviceMapping { start: usize, len: usize },
+    StepLimitExceeded { limit: u64 },
+    MemoryLimitExceeded { address: usize, limit: usize },
+    OutputLimitExceeded { limit: u64 },
+    Timeout { limit: Duration },
+    /// Network node `node` executed `steps` instructions without input or
+    /// output.
+    Unresponsive { node: usize, steps: u64 },
+    /// The program came back to an earlier state and will repeat the
+    /// instructions between `start` and `end` every `period` steps forever.
+    InfiniteLoop { start: usize, end: usize, period: u64 },
+}
+
+impl IntcodeError {
+    /// Attach the address of the offending word to a decoding failure.
+    pub fn decode(address: usize, word: i64, error: DecodeError) -> IntcodeError {
+        match error {
+            DecodeError::InvalidParameterMode(mode) => Self::InvalidParameterMode { address, mode },
+            DecodeError::Overflow(_) | DecodeError::UnknownOpcode(_) => Self::UnknownOpcode {
+                address,
+                opcode: word,
+            },
+        }
+    }
+}
+
+impl fmt::Display for IntcodeError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::UnknownOpcode { address, opcode } => {
+                write!(f, "unknown opcode {} at address {}", opcode, address)
+            },
+            Self::InvalidParameterMode { address, mode } => {
+                write!(f, "unsupported parameter mode {} at address {}", mode, address)
+            },
+            Self::OutOfBoundsWrite { address } => write!(f, "unable to grow memory to address {}", address),
+            Self::TooManyWrites { address } => write!(f, "instruction at address {} wrote too many cells", address),
+            Self::NegativeAddress { value } => write!(f, "negative address {}", value),
+            Self::ArithmeticOverflow { address } => write!(f, "arithmetic overflow at address {}", address),
+            Self::Parse { column, token } => write!(f, "invalid value {:?} at column {}", token, column),
+            Self::InputUnavailable => write!(f, "no input available"),
+            Self::OutputUnavailable => write!(f, "output device is closed"),
+            Self::MissingOutput => write!(f, "program halted without producing output"),
+            Self::Device { message } => write!(f, "device error: {}", message),
+            Self::InvalidDeviceMapping { start, len } => {
+                write!(f, "cannot map a device of {} cells at address {}", len, start)
+            },
+            Self::StepLimitExceeded { limit } => write!(f, "exceeded the limit of {} steps", limit),
+            Self::MemoryLimitExceeded { address, limit } => {
+                write!(f, "write to address {} exceeds the limit of {} memory cells", address, limit)
+            },
+            Self::OutputLimitExceeded { limit } => write!(f, "exceeded the limit of {} outputs", limit),
+            Self::Timeout { limit } => write!(f, "timed out after {:?}", limit),
+            Self::Unresponsive { node, steps } => {
+                write!(f, "node {} executed {} instructions without input or output", node, steps)
+            },
+            Self::InfiniteLoop { start, end, period } => {
+                write!(f, "infinite loop between addresses {} and {} repeating every {} steps", start, end - 1, period)
+            },
+        }
+    }
+}
+
+impl std::error::Error for IntcodeError {}
@@ -0,0 +1,538 @@
+//! Instructions as handlers registered on a program by opcode number.
+//!
+//! `Program::execute` decodes parameter modes and resolves parameters with
+//! the arity and write parameters of the handler registered for the opcode,
+//! hands the values to the handler and applies the `Effect` it returns. The
+//! built-in instructions are handlers like any other, so registering a
+//! handler for a built-in number replaces it:
+//!
+//! ```
+//! use intcode::handler::{Context, Effect, InstructionHandler};
+//! use intcode::{IntcodeError, Program, Status};
+//!
+//! /// `sq a, dst` squares a value.
+//! struct Square;
+//!
+//! impl InstructionHandler for Square {
+//!     fn arity(&self) -> usize {
+//!         2
+//!     }
+//!
+//!     fn write_parameters(&self) -> &[usize] {
+//!         &[1]
+//!     }
+//!
+//!     fn execute(&self, context: &mut Context, parameters: &[i64]) -> Result<Effect, IntcodeError> {
+//!         context.write(parameters[1] as usize, parameters[0] * parameters[0])?;
+//!
+//!         Ok(Effect::Next)
+//!     }
+//! }
+//!
+//! let mut program = Program::from(vec![142, 12, 6, 4, 6, 99, 0]);
+//! program.register_instruction(42, Square);
+//!
+//! assert_eq!(program.run(), Ok(Status::Output(144)));
+//! ```
+//!
+//! Handlers only reach the program through a `Context`, which records every
+//! write and consumed input in the `Execution`, so history, watchpoints and
+//! tracers see everything an instruction did. When a handler fails, those
+//! writes and the consumed input are undone again.
+
+use std::collections::{BTreeMap, VecDeque};
+use std::fmt;
+use std::ops::Deref;
+use std::sync::{Arc, OnceLock};
+
+use crate::error::IntcodeError;
+use crate::program::{to_address, Execution, MemoryWrite, Program};
+
+/// Most memory cells a single instruction may write.
+pub const MAX_WRITES: usize = 3;
+
+/// What the program does after a handler executed.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Effect {
+    /// Continue with the following instruction.
+    Next,
+    /// Emit the value.
+    Output(i64),
+    /// Continue at the address.
+    Jump(usize),
+    /// Leave the instruction unexecuted until input is pushed. Handlers
+    /// return it before writing or taking input.
+    NeedsInput,
+    Halt,
+}
+
+pub trait InstructionHandler: Send + Sync {
+    /// Number of parameters following the opcode, at most three.
+    fn arity(&self) -> usize;
+
+    /// Positions of the parameters the instruction writes to. They resolve
+    /// to destination addresses instead of values.
+    fn write_parameters(&self) -> &[usize] {
+        &[]
+    }
+
+    /// Execute the instruction with its resolved parameters.
+    fn execute(&self, context: &mut Context, parameters: &[i64]) -> Result<Effect, IntcodeError>;
+}
+
+/// The program as an executing handler sees it.
+pub struct Context<'a> {
+    program: &'a mut Program,
+    execution: &'a mut Execution,
+}
+
+impl<'a> Context<'a> {
+    pub(crate) fn new(program: &'a mut Program, execution: &'a mut Execution) -> Context<'a> {
+        Context { program, execution }
+    }
+
+    /// Address of the executing instruction.
+    pub fn address(&self) -> usize {
+        self.execution.address
+    }
+
+    pub fn relative_base(&self) -> i64 {
+        self.program.relative_base
+    }
+
+    pub fn set_relative_base(&mut self, relative_base: i64) {
+        self.program.relative_base = relative_base;
+    }
+
+    pub fn read(&self, address: usize) -> Result<i64, IntcodeError> {
+        self.program.value(address)
+    }
+
+    /// Write a memory cell and record the write. Fails with
+    /// `IntcodeError::TooManyWrites` beyond `MAX_WRITES` writes.
+    pub fn write(&mut self, address: usize, value: i64) -> Result<(), IntcodeError> {
+        if self.execution.writes().len() == MAX_WRITES {
+            return Err(IntcodeError::TooManyWrites { address: self.address() });
+        }
+
+        let old = self.program.memory().get(address).copied().unwrap_or(0);
+        self.program.write(address, value)?;
+        self.execution.record_write(MemoryWrite { address, old, new: value });
+
+        Ok(())
+    }
+
+    pub fn pending_input(&self) -> &VecDeque<i64> {
+        self.program.pending_input()
+    }
+
+    /// Consume the next queued input value.
+    pub fn take_input(&mut self) -> Option<i64> {
+        let value = self.program.take_input()?;
+        self.execution.input = Some(value);
+
+        Some(value)
+    }
+}
+
+/// A registered handler. Built-in handlers are statics, so looking one up
+/// for every executed instruction costs no reference counting.
+#[derive(Clone)]
+pub(crate) enum Handler {
+    Builtin(&'static dyn InstructionHandler),
+    Registered(Arc<dyn InstructionHandler>),
+}
+
+impl Deref for Handler {
+    type Target = dyn InstructionHandler;
+
+    fn deref(&self) -> &Self::Target {
+        match self {
+            Self::Builtin(handler) => *handler,
+            Self::Registered(handler) => handler.as_ref(),
+        }
+    }
+}
+
+/// Handlers by opcode number.
+#[derive(Clone)]
+pub(crate) struct InstructionSet {
+    handlers: Vec<Option<Handler>>,
+}
+
+impl InstructionSet {
+    /// The built-in instructions, shared between every program that does
+    /// not register its own.
+    pub(crate) fn builtin() -> Arc<InstructionSet> {
+        static BUILTIN: OnceLock<Arc<InstructionSet>> = OnceLock::new();
+
+        BUILTIN.get_or_init(|| {
+            let mut set = InstructionSet { handlers: vec![None; 100] };
+            set.register(1, Handler::Builtin(&Add));
+            set.register(2, Handler::Builtin(&Multiply));
+            set.register(3, Handler::Builtin(&Input));
+            set.register(4, Handler::Builtin(&Output));
+            set.register(5, Handler::Builtin(&Jump { if_true: true }));
+            set.register(6, Handler::Builtin(&Jump { if_true: false }));
+            set.register(7, Handler::Builtin(&LessThan));
+            set.register(8, Handler::Builtin(&Equals));
+            set.register(9, Handler::Builtin(&AdjustRelativeBase));
+            set.register(99, Handler::Builtin(&Halt));
+
+            Arc::new(set)
+        }).clone()
+    }
+
+    pub(crate) fn register(&mut self, code: u32, handler: Handler) {
+        assert!(code < 100, "opcode {} does not fit in two digits", code);
+        assert!(handler.arity() <= 3, "opcode {} takes more than three parameters", code);
+        assert!(
+            handler.write_parameters().iter().all(|position| *position < handler.arity()),
+            "opcode {} writes to a parameter it does not take",
+            code,
+        );
+
+        self.handlers[code as usize] = Some(handler);
+    }
+
+    pub(crate) fn get(&self, code: u32) -> Option<&Handler> {
+        self.handlers.get(code as usize).and_then(Option::as_ref)
+    }
+}
+
+impl fmt::Debug for InstructionSet {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let registered: BTreeMap<usize, usize> = self.handlers.iter()
+            .enumerate()
+            .filter_map(|(code, handler)| handler.as_ref().map(|handler| (code, handler.arity())))
+            .collect();
+
+        f.debug_struct("InstructionSet").field("arities", &registered).finish()
+    }
+}
+
+fn overflow(context: &Context) -> IntcodeError {
+    IntcodeError::ArithmeticOverflow { address: context.address() }
+}
+
+struct Add;
+
+impl InstructionHandler for Add {
+    fn arity(&self) -> usize {
+        3
+    }
+
+    fn write_parameters(&self) -> &[usize] {
+        &[2]
+    }
+
+    fn execute(&self, context: &mut Context, parameters: &[i64]) -> Result<Effect, IntcodeError> {
+        let sum = parameters[0].checked_add(parameters[1]).ok_or_else(|| overflow(context))?;
+        context.write(parameters[2] as usize, sum)?;
+
+        Ok(Effect::Next)
+    }
+}
+
+struct Multiply;
+
+impl InstructionHandler for Multiply {
+    fn arity(&self) -> usize {
+        3
+    }
+
+    fn write_parameters(&self) -> &[usize] {
+        &[2]
+    }
+
+    fn execute(&self, context: &mut Context, parameters: &[i64]) -> Result<Effect, IntcodeError> {
+        let product = parameters[0].checked_mul(parameters[1]).ok_or_else(|| overflow(context))?;
+        context.write(parameters[2] as usize, product)?;
+
+        Ok(Effect::Next)
+    }
+}
+
+struct Input;
+
+impl InstructionHandler for Input {
+    fn arity(&self) -> usize {
+        1
+    }
+
+    fn write_parameters(&self) -> &[usize] {
+        &[0]
+    }
+
+    fn execute(&self, context: &mut Context, parameters: &[i64]) -> Result<Effect, IntcodeError> {
+        match context.take_input() {
+            Some(value) => {
+                context.write(parameters[0] as usize, value)?;
+                Ok(Effect::Next)
+            },
+            None => Ok(Effect::NeedsInput),
+        }
+    }
+}
+
+struct Output;
+
+impl InstructionHandler for Output {
+    fn arity(&self) -> usize {
+        1
+    }
+
+    fn execute(&self, _: &mut Context, parameters: &[i64]) -> Result<Effect, IntcodeError> {
+        Ok(Effect::Output(parameters[0]))
+    }
+}
+
+/// `jnz` and `jz`.
+struct Jump {
+    if_true: bool,
+}
+
+impl InstructionHandler for Jump {
+    fn arity(&self) -> usize {
+        2
+    }
+
+    fn execute(&self, _: &mut Context, parameters: &[i64]) -> Result<Effect, IntcodeError> {
+        if (parameters[0] != 0) == self.if_true {
+            Ok(Effect::Jump(to_address(parameters[1])?))
+        } else {
+            Ok(Effect::Next)
+        }
+    }
+}
+
+struct LessThan;
+
+impl InstructionHandler for LessThan {
+    fn arity(&self) -> usize {
+        3
+    }
+
+    fn write_parameters(&self) -> &[usize] {
+        &[2]
+    }
+
+    fn execute(&self, context: &mut Context, parameters: &[i64]) -> Result<Effect, IntcodeError> {
+        context.write(parameters[2] as usize, (parameters[0] < parameters[1]) as i64)?;
+
+        Ok(Effect::Next)
+    }
+}
+
+struct Equals;
+
+impl InstructionHandler for Equals {
+    fn arity(&self) -> usize {
+        3
+    }
+
+    fn write_parameters(&self) -> &[usize] {
+        &[2]
+    }
+
+    fn execute(&self, context: &mut Context, parameters: &[i64]) -> Result<Effect, IntcodeError> {
+        context.write(parameters[2] as usize, (parameters[0] == parameters[1]) as i64)?;
+
+        Ok(Effect::Next)
+    }
+}
+
+struct AdjustRelativeBase;
+
+impl InstructionHandler for AdjustRelativeBase {
+    fn arity(&self) -> usize {
+        1
+    }
+
+    fn execute(&self, context: &mut Context, parameters: &[i64]) -> Result<Effect, IntcodeError> {
+        let relative_base = context.relative_base().checked_add(parameters[0]).ok_or_else(|| overflow(context))?;
+        context.set_relative_base(relative_base);
+
+        Ok(Effect::Next)
+    }
+}
+
+struct Halt;
+
+impl InstructionHandler for Halt {
+    fn arity(&self) -> usize {
+        0
+    }
+
+    fn execute(&self, _: &mut Context, _: &[i64]) -> Result<Effect, IntcodeError> {
+        Ok(Effect::Halt)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::history::History;
+    use crate::instruction::Opcode;
+    use crate::limits::Limits;
+    use crate::program::Status;
+    use std::collections::VecDeque;
+
+    /// `swap a, b` exchanges two cells, reading both operands as addresses.
+    struct Swap;
+
+    impl InstructionHandler for Swap {
+        fn arity(&self) -> usize {
+            2
+        }
+
+        fn execute(&self, context: &mut Context, parameters: &[i64]) -> Result<Effect, IntcodeError> {
+            let (a, b) = (to_address(parameters[0])?, to_address(parameters[1])?);
+            let (left, right) = (context.read(a)?, context.read(b)?);
+            context.write(a, right)?;
+            context.write(b, left)?;
+
+            Ok(Effect::Next)
+        }
+    }
+
+    /// `dup a, x, y` copies a value to two destinations.
+    struct Duplicate;
+
+    impl InstructionHandler for Duplicate {
+        fn arity(&self) -> usize {
+            3
+        }
+
+        fn write_parameters(&self) -> &[usize] {
+            &[1, 2]
+        }
+
+        fn execute(&self, context: &mut Context, parameters: &[i64]) -> Result<Effect, IntcodeError> {
+            context.write(parameters[1] as usize, parameters[0])?;
+            context.write(parameters[2] as usize, parameters[0])?;
+
+            Ok(Effect::Next)
+        }
+    }
+
+    /// `clr` zeroes the first cells of memory, more than one instruction may.
+    struct Clear;
+
+    impl InstructionHandler for Clear {
+        fn arity(&self) -> usize {
+            0
+        }
+
+        fn execute(&self, context: &mut Context, _: &[i64]) -> Result<Effect, IntcodeError> {
+            for address in 0..=MAX_WRITES {
+                context.write(address, 0)?;
+            }
+
+            Ok(Effect::Next)
+        }
+    }
+
+    #[test]
+    fn it_runs_registered_instructions() {
+        let mut program = Program::from(vec![1150, 7, 8, 4, 7, 99, 0, 1, 2]);
+        program.register_instruction(50, Swap);
+
+        let execution = program.execute().unwrap();
+        assert_eq!(execution.opcode, Opcode::Custom(50));
+        assert_eq!(execution.parameters(), &[7, 8]);
+        assert_eq!(execution.writes(), &[
+            MemoryWrite { address: 7, old: 1, new: 2 },
+            MemoryWrite { address: 8, old: 2, new: 1 },
+        ]);
+        assert_eq!(program.run(), Ok(Status::Output(2)));
+        assert_eq!(program.memory()[7..], [2, 1]);
+    }
+
+    #[test]
+    fn it_replaces_built_in_instructions() {
+        let mut program = Program::from(vec![1101, 2, 3, 0, 4, 0, 99]);
+        program.register_instruction(1, Multiply);
+        let mut outputs = vec![];
+        program.run_with(&mut VecDeque::new(), &mut outputs).unwrap();
+
+        assert_eq!(outputs, vec![6]);
+        assert_eq!(Program::from(vec![1101, 2, 3, 0, 4, 0, 99]).run(), Ok(Status::Output(5)));
+    }
+
+    #[test]
+    fn it_undoes_writes_made_by_registered_instructions() {
+        let mut program = Program::from(vec![1150, 4, 5, 99, 1, 2]);
+        program.register_instruction(50, Swap);
+        let mut history = History::new(program);
+
+        history.step().unwrap();
+        assert_eq!(history.program().memory()[4..], [2, 1]);
+        assert_eq!(history.last_write(5).map(|entry| entry.step), Some(0));
+
+        history.step_back();
+        assert_eq!(history.program().memory()[4..], [1, 2]);
+    }
+
+    #[test]
+    fn it_resolves_every_write_parameter_to_an_address() {
+        let mut program = Program::from(vec![151, 9, 6, 7, 99, 0, 0, 0]);
+        program.register_instruction(51, Duplicate);
+
+        let execution = program.execute().unwrap();
+        assert_eq!(execution.parameters(), &[9, 6, 7]);
+        assert_eq!(execution.writes().iter().map(|write| write.address).collect::<Vec<_>>(), vec![6, 7]);
+        assert_eq!(program.memory()[6..], [9, 9]);
+    }
+
+    #[test]
+    fn it_limits_the_writes_of_an_instruction() {
+        let mut program = Program::from(vec![52, 99]);
+        program.register_instruction(52, Clear);
+
+        assert_eq!(program.execute(), Err(IntcodeError::TooManyWrites { address: 0 }));
+        assert_eq!(program.memory(), &[52, 99]);
+    }
+
+    #[test]
+    fn it_leaves_the_program_untouched_when_an_instruction_fails() {
+        let mut program = Program::from(vec![3, 100, 99]);
+        program.set_limits(Limits::new().memory(10));
+        program.push_input(7);
+
+        assert_eq!(program.execute(), Err(IntcodeError::MemoryLimitExceeded { address: 100, limit: 10 }));
+        assert_eq!(program.memory(), &[3, 100, 99]);
+        assert_eq!(program.pending_input(), &[7]);
+        assert_eq!(program.index, 0);
+    }
+
+    #[test]
+    #[should_panic(expected = "opcode 53 writes to a parameter it does not take")]
+    fn it_refuses_write_parameters_beyond_the_arity() {
+        struct Broken;
+
+        impl InstructionHandler for Broken {
+            fn arity(&self) -> usize {
+                1
+            }
+
+            fn write_parameters(&self) -> &[usize] {
+                &[1]
+            }
+
+            fn execute(&self, _: &mut Context, _: &[i64]) -> Result<Effect, IntcodeError> {
+                Ok(Effect::Next)
+            }
+        }
+
+        Program::from(vec![99]).register_instruction(53, Broken);
+    }
+
+    #[test]
+    fn it_rejects_unregistered_opcodes() {
+        assert_eq!(Program::from(vec![50, 0, 0]).run(), Err(IntcodeError::UnknownOpcode {
+            address: 0,
+            opcode: 50,
+        }));
+    }
+}
@@ -0,0 +1,221 @@
+//! Reverse execution on top of `Program::execute`.
+//!
+//! Every executed instruction is kept in an undo log together with the
+//! registers it started from, so execution can be stepped backwards or
+//! rewound to an earlier step, and the log can be searched for the last
+//! instruction that wrote a given address. The log grows with every step;
+//! it is meant for debugging, not for long production runs. Stepping back
+//! also gives back the steps, outputs and time counted against limits.
+//! Mapped devices keep their state when stepping backwards.
+
+use crate::error::IntcodeError;
+use crate::program::{Execution, Program, Status, Usage};
+
+/// An executed instruction and what is needed to undo it.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Entry {
+    /// Number of instructions executed before this one.
+    pub step: u64,
+    pub execution: Execution,
+    relative_base: i64,
+    usage: Usage,
+    /// Memory size before and after the instruction executed.
+    memory_len: usize,
+    grown_len: usize,
+}
+
+#[derive(Debug, Clone)]
+pub struct History {
+    program: Program,
+    log: Vec<Entry>,
+}
+
+impl History {
+    pub fn new(program: Program) -> History {
+        History {
+            program,
+            log: vec![],
+        }
+    }
+
+    pub fn program(&self) -> &Program {
+        &self.program
+    }
+
+    /// Direct access to the program. Changes made through it are not
+    /// recorded and survive stepping backwards.
+    pub fn program_mut(&mut self) -> &mut Program {
+        &mut self.program
+    }
+
+    /// Number of instructions executed and recorded so far.
+    pub fn steps(&self) -> u64 {
+        self.log.len() as u64
+    }
+
+    pub fn entries(&self) -> &[Entry] {
+        &self.log
+    }
+
+    /// Execute one instruction and record it. Instructions that leave the
+    /// program untouched, waiting for input or halting, are not recorded.
+    pub fn step(&mut self) -> Result<Execution, IntcodeError> {
+        let relative_base = self.program.relative_base;
+        let usage = self.program.usage();
+        let memory_len = self.program.memory().len();
+        let execution = self.program.execute()?;
+
+        match execution.status {
+            Some(Status::NeedsInput) | Some(Status::Halted) => {},
+            _ => self.log.push(Entry {
+                step: self.log.len() as u64,
+                execution: execution.clone(),
+                relative_base,
+                usage,
+                memory_len,
+                grown_len: self.program.memory().len(),
+            }),
+        }
+
+        Ok(execution)
+    }
+
+    /// Undo the most recent instruction, returning what it had done.
+    pub fn step_back(&mut self) -> Option<Entry> {
+        let entry = self.log.pop()?;
+        let execution = &entry.execution;
+
+        // Undo in reverse, so a cell written twice ends up with the value
+        // from before the first write.
+        for write in execution.writes().iter().rev() {
+            self.program.restore(write.address, write.old);
+        }
+        // Memory grown since, through `program_mut`, stays.
+        if self.program.memory().len() == entry.grown_len {
+            self.program.truncate_memory(entry.memory_len);
+        }
+
+        if let Some(value) = execution.input {
+            self.program.unread_input(value);
+        }
+
+        self.program.index = execution.address;
+        self.program.relative_base = entry.relative_base;
+        self.program.set_usage(entry.usage);
+
+        Some(entry)
+    }
+
+    /// Undo instructions until only the first `step` remain executed,
+    /// returning the undone entries, most recent first.
+    pub fn rewind(&mut self, step: u64) -> Vec<Entry> {
+        let mut undone = vec![];
+
+        while self.steps() > step {
+            undone.extend(self.step_back());
+        }
+
+        undone
+    }
+
+    /// The most recent instruction that wrote to `address`.
+    pub fn last_write(&self, address: usize) -> Option<&Entry> {
+        self.log.iter()
+            .rev()
+            .find(|entry| entry.execution.writes().iter().any(|write| write.address == address))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::limits::Limits;
+    use crate::program::parse_program_into_instructions;
+
+    fn history(source: &str) -> History {
+        History::new(Program::from(parse_program_into_instructions(source).unwrap()))
+    }
+
+    fn run(history: &mut History) -> Vec<i64> {
+        let mut outputs = vec![];
+
+        loop {
+            match history.step().unwrap().status {
+                Some(Status::Output(value)) => outputs.push(value),
+                Some(_) => return outputs,
+                None => {},
+            }
+        }
+    }
+
+    #[test]
+    fn it_steps_back_to_the_initial_state() {
+        let quine = "109,1,204,-1,1001,100,1,100,1008,100,16,101,1006,101,0,99";
+        let mut history = history(quine);
+        let initial = history.program().clone();
+
+        assert_eq!(run(&mut history), parse_program_into_instructions(quine).unwrap());
+        assert!(history.program().memory().len() > 100);
+
+        let steps = history.steps();
+        assert_eq!(history.rewind(0).len() as u64, steps);
+        assert_eq!(history.program(), &initial);
+        assert_eq!(history.step_back(), None);
+    }
+
+    #[test]
+    fn it_puts_consumed_input_back() {
+        let mut history = history("3,9,8,9,10,9,4,9,99,-1,8");
+        history.program_mut().push_input(8);
+
+        assert_eq!(run(&mut history), vec![1]);
+        history.rewind(1);
+
+        assert_eq!(history.program().index, 2);
+        assert_eq!(history.program()[9], 8);
+
+        let entry = history.step_back().unwrap();
+        assert_eq!(entry.execution.input, Some(8));
+        assert_eq!(history.program().pending_input().iter().collect::<Vec<_>>(), vec![&8]);
+        assert_eq!(history.program()[9], -1);
+    }
+
+    #[test]
+    fn it_finds_the_last_instruction_writing_an_address() {
+        let mut history = history("1101,1,2,9,1102,3,4,9,99,0");
+        run(&mut history);
+
+        let entry = history.last_write(9).unwrap();
+        assert_eq!(entry.step, 1);
+        assert_eq!(entry.execution.address, 4);
+        assert_eq!(history.last_write(0), None);
+
+        history.step_back();
+        assert_eq!(history.last_write(9).map(|entry| entry.execution.address), Some(0));
+    }
+
+    #[test]
+    fn it_gives_back_what_undone_instructions_counted_against_limits() {
+        let mut history = history("104,1,104,2,99");
+        history.program_mut().set_limits(Limits::new().steps(2).outputs(1));
+
+        assert_eq!(history.step().unwrap().status, Some(Status::Output(1)));
+        history.step_back();
+
+        assert_eq!(history.step().unwrap().status, Some(Status::Output(1)));
+        assert_eq!(history.step(), Err(IntcodeError::OutputLimitExceeded { limit: 1 }));
+    }
+
+    #[test]
+    fn it_keeps_memory_grown_through_the_program() {
+        let mut history = history("1101,1,2,20,99");
+        history.step().unwrap();
+        history.program_mut().write(30, 7).unwrap();
+
+        history.step_back();
+
+        assert_eq!(history.program().memory().len(), 31);
+        assert_eq!(history.program()[20], 0);
+        assert_eq!(history.program()[30], 7);
+    }
+}
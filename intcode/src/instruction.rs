@@ -0,0 +1,323 @@
+use std::convert::TryFrom;
+
+use crate::error::DecodeError;
+
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum ParameterMode {
+    Position,
+    Immediate,
+    Relative,
+}
+
+impl TryFrom<u32> for ParameterMode {
+    type Error = DecodeError;
+
+    fn try_from(input: u32) -> Result<ParameterMode, DecodeError> {
+        match input {
+            0 => Ok(ParameterMode::Position),
+            1 => Ok(ParameterMode::Immediate),
+            2 => Ok(ParameterMode::Relative),
+            mode => Err(DecodeError::InvalidParameterMode(mode)),
+        }
+    }
+}
+
+#[derive(Debug, PartialEq, Eq, Hash, Clone, Copy)]
+pub enum Opcode {
+    Addition,
+    Multiplication,
+    JumpIfTrue,
+    JumpIfFalse,
+    LessThen,
+    Equals,
+    Input,
+    Output,
+    AdjustRelativeBase,
+    Exit,
+    /// Any other opcode number, executed by an `InstructionHandler`
+    /// registered for it. Only the handler knows its parameters, so
+    /// `arity` reports none and `write_parameter` nothing.
+    Custom(u32),
+}
+
+impl Opcode {
+    /// Number of parameters following the opcode in memory.
+    pub fn arity(&self) -> usize {
+        match self {
+            Self::Addition | Self::Multiplication | Self::LessThen | Self::Equals => 3,
+            Self::JumpIfTrue | Self::JumpIfFalse => 2,
+            Self::Input | Self::Output | Self::AdjustRelativeBase => 1,
+            Self::Exit | Self::Custom(_) => 0,
+        }
+    }
+
+    /// Numeric code of the opcode, without any parameter modes.
+    pub fn code(&self) -> u32 {
+        match self {
+            Self::Addition => 1,
+            Self::Multiplication => 2,
+            Self::Input => 3,
+            Self::Output => 4,
+            Self::JumpIfTrue => 5,
+            Self::JumpIfFalse => 6,
+            Self::LessThen => 7,
+            Self::Equals => 8,
+            Self::AdjustRelativeBase => 9,
+            Self::Exit => 99,
+            Self::Custom(code) => *code,
+        }
+    }
+
+    /// Position of the parameter the instruction writes its result to.
+    pub fn write_parameter(&self) -> Option<usize> {
+        match self {
+            Self::Addition | Self::Multiplication | Self::LessThen | Self::Equals => Some(2),
+            Self::Input => Some(0),
+            _ => None,
+        }
+    }
+
+    /// Every opcode, in numeric order.
+    pub fn all() -> [Opcode; 10] {
+        [
+            Self::Addition,
+            Self::Multiplication,
+            Self::Input,
+            Self::Output,
+            Self::JumpIfTrue,
+            Self::JumpIfFalse,
+            Self::LessThen,
+            Self::Equals,
+            Self::AdjustRelativeBase,
+            Self::Exit,
+        ]
+    }
+
+    /// The built-in opcode numbered `code`, or `Opcode::Custom`.
+    pub fn from_code(code: u32) -> Opcode {
+        Opcode::try_from(code).unwrap_or(Opcode::Custom(code))
+    }
+
+    /// Look up an opcode by the name returned from `Opcode::mnemonic`.
+    pub fn from_mnemonic(mnemonic: &str) -> Option<Opcode> {
+        Self::all().iter().copied().find(|opcode| opcode.mnemonic() == mnemonic)
+    }
+
+    /// Short assembly name used by listings.
+    pub fn mnemonic(&self) -> &'static str {
+        match self {
+            Self::Addition => "add",
+            Self::Multiplication => "mul",
+            Self::Input => "in",
+            Self::Output => "out",
+            Self::JumpIfTrue => "jnz",
+            Self::JumpIfFalse => "jz",
+            Self::LessThen => "lt",
+            Self::Equals => "eq",
+            Self::AdjustRelativeBase => "arb",
+            Self::Exit => "hlt",
+            Self::Custom(_) => "custom",
+        }
+    }
+}
+
+impl TryFrom<u32> for Opcode {
+    type Error = DecodeError;
+
+    fn try_from(number: u32) -> Result<Opcode, DecodeError> {
+        match number {
+            1 => Ok(Self::Addition),
+            2 => Ok(Self::Multiplication),
+            3 => Ok(Self::Input),
+            4 => Ok(Self::Output),
+            5 => Ok(Self::JumpIfTrue),
+            6 => Ok(Self::JumpIfFalse),
+            7 => Ok(Self::LessThen),
+            8 => Ok(Self::Equals),
+            9 => Ok(Self::AdjustRelativeBase),
+            99 => Ok(Self::Exit),
+            opcode => Err(DecodeError::UnknownOpcode(opcode)),
+        }
+    }
+}
+
+/// A decoded opcode word. Decoding never allocates, so instructions are
+/// cheap to copy and to cache.
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub struct Instruction {
+    pub opcode: Opcode,
+    parameters: [Parameter; 3],
+    arity: usize,
+}
+
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub struct Parameter {
+    pub mode: ParameterMode,
+    pub position: usize,
+}
+
+impl Instruction {
+    /// Split an opcode word into the modes of its three parameters, from
+    /// the first to the third, followed by the opcode number.
+    pub fn normalize(input: i64) -> Result<[u32; 4], DecodeError> {
+        if !(0..=99999).contains(&input) {
+            return Err(DecodeError::Overflow(input))
+        }
+
+        let input = input as u32;
+
+        Ok([
+            input / 10000,
+            input / 1000 % 10,
+            input / 100 % 10,
+            input % 100,
+        ])
+    }
+
+    pub fn parse(input: i64) -> Result<Instruction, DecodeError> {
+        let opcode = Opcode::try_from(Self::normalize(input)?[3])?;
+
+        Self::parse_with_arity(input, opcode.arity())
+    }
+
+    /// Decode `input` as an instruction taking `arity` parameters, whatever
+    /// its opcode number. Numbers outside the built-in set decode as
+    /// `Opcode::Custom`.
+    pub(crate) fn parse_with_arity(input: i64, arity: usize) -> Result<Instruction, DecodeError> {
+        let digits = Self::normalize(input)?;
+        let opcode = Opcode::from_code(digits[3]);
+
+        let mut parameters = [
+            Parameter { mode: ParameterMode::Position, position: 0 },
+            Parameter { mode: ParameterMode::Position, position: 1 },
+            Parameter { mode: ParameterMode::Position, position: 2 },
+        ];
+        for parameter in parameters.iter_mut().take(arity) {
+            parameter.mode = ParameterMode::try_from(digits[2 - parameter.position])?;
+        }
+
+        Ok(Instruction {
+            opcode,
+            parameters,
+            arity,
+        })
+    }
+
+    /// Parameters of the instruction, one per `Opcode::arity` for built-in
+    /// opcodes.
+    pub fn parameters(&self) -> &[Parameter] {
+        &self.parameters[..self.arity]
+    }
+
+    /// Number of memory cells occupied by the opcode and its parameters.
+    pub fn size(&self) -> usize {
+        1 + self.arity
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn it_can_normalize_instruction() {
+        assert_eq!(Instruction::normalize(111112), Err(DecodeError::Overflow(111112)));
+        assert_eq!(Instruction::normalize(-1), Err(DecodeError::Overflow(-1)));
+        assert_eq!(Instruction::normalize(11112), Ok([1, 1, 1, 12]));
+        assert_eq!(Instruction::normalize(1003), Ok([0, 1, 0, 3]));
+        assert_eq!(Instruction::normalize(99), Ok([0, 0, 0, 99]));
+        assert_eq!(Instruction::normalize(1003), Ok([0, 1, 0, 3]));
+        assert_eq!(Instruction::normalize(21001), Ok([2, 1, 0, 1]));
+    }
+
+    #[test]
+    fn it_parses_opcode_instructions() {
+        let instruction = Instruction::parse(2).unwrap();
+        assert_eq!(instruction.opcode, Opcode::Multiplication);
+        assert_eq!(instruction.parameters(), &[
+            Parameter {
+                mode: ParameterMode::Position,
+                position: 0,
+            },
+            Parameter {
+                mode: ParameterMode::Position,
+                position: 1,
+            },
+            Parameter {
+                mode: ParameterMode::Position,
+                position: 2,
+            },
+        ]);
+
+        let instruction = Instruction::parse(102).unwrap();
+        assert_eq!(instruction.opcode, Opcode::Multiplication);
+        assert_eq!(instruction.parameters(), &[
+            Parameter {
+                mode: ParameterMode::Immediate,
+                position: 0,
+            },
+            Parameter {
+                mode: ParameterMode::Position,
+                position: 1,
+            },
+            Parameter {
+                mode: ParameterMode::Position,
+                position: 2,
+            },
+        ]);
+
+        let instruction = Instruction::parse(1002).unwrap();
+        assert_eq!(instruction.opcode, Opcode::Multiplication);
+        assert_eq!(instruction.parameters(), &[
+            Parameter {
+                mode: ParameterMode::Position,
+                position: 0,
+            },
+            Parameter {
+                mode: ParameterMode::Immediate,
+                position: 1,
+            },
+            Parameter {
+                mode: ParameterMode::Position,
+                position: 2,
+            },
+        ]);
+
+        let instruction = Instruction::parse(3).unwrap();
+        assert_eq!(instruction.opcode, Opcode::Input);
+        assert_eq!(instruction.parameters(), &[
+            Parameter {
+                mode: ParameterMode::Position,
+                position: 0,
+            },
+        ]);
+    }
+
+    #[test]
+    fn it_honours_parameter_modes_for_output() {
+        let instruction = Instruction::parse(104).unwrap();
+        assert_eq!(instruction.opcode, Opcode::Output);
+        assert_eq!(instruction.parameters(), &[
+            Parameter {
+                mode: ParameterMode::Immediate,
+                position: 0,
+            },
+        ]);
+    }
+
+    #[test]
+    fn it_round_trips_opcode_codes_and_mnemonics() {
+        for opcode in Opcode::all().iter() {
+            assert_eq!(Opcode::try_from(opcode.code()), Ok(*opcode));
+            assert_eq!(Opcode::from_mnemonic(opcode.mnemonic()), Some(*opcode));
+        }
+    }
+
+    #[test]
+    fn it_rejects_unknown_opcodes_and_modes() {
+        assert_eq!(Instruction::parse(42), Err(DecodeError::UnknownOpcode(42)));
+        assert_eq!(Instruction::parse(301), Err(DecodeError::InvalidParameterMode(3)));
+        assert_eq!(Instruction::parse(20099).map(|instruction| instruction.opcode), Ok(Opcode::Exit));
+        assert_eq!(Instruction::parse(20099), Instruction::parse(99));
+    }
+}
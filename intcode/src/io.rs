@@ -0,0 +1,125 @@
+use std::collections::VecDeque;
+use std::io::{self, BufRead};
+use std::sync::mpsc::{Receiver, Sender};
+
+use crate::error::IntcodeError;
+
+/// Source of values for `Opcode::Input`.
+pub trait InputDevice {
+    fn read(&mut self) -> Result<i64, IntcodeError>;
+}
+
+/// Destination of values emitted by `Opcode::Output`.
+pub trait OutputDevice {
+    fn write(&mut self, value: i64) -> Result<(), IntcodeError>;
+}
+
+/// A fixed queue of values, consumed front to back.
+impl InputDevice for VecDeque<i64> {
+    fn read(&mut self) -> Result<i64, IntcodeError> {
+        self.pop_front().ok_or(IntcodeError::InputUnavailable)
+    }
+}
+
+/// Blocks until the sending half delivers a value.
+impl InputDevice for Receiver<i64> {
+    fn read(&mut self) -> Result<i64, IntcodeError> {
+        self.recv().map_err(|_| IntcodeError::InputUnavailable)
+    }
+}
+
+/// Reads one value per line, skipping blank lines.
+pub struct LineInput<R> {
+    reader: R,
+}
+
+impl<R: BufRead> LineInput<R> {
+    pub fn new(reader: R) -> LineInput<R> {
+        LineInput { reader }
+    }
+}
+
+impl LineInput<io::StdinLock<'static>> {
+    pub fn stdin() -> Self {
+        LineInput::new(io::stdin().lock())
+    }
+}
+
+impl<R: BufRead> InputDevice for LineInput<R> {
+    fn read(&mut self) -> Result<i64, IntcodeError> {
+        let mut line = String::new();
+
+        loop {
+            line.clear();
+            let read = self.reader.read_line(&mut line)
+                .map_err(|error| IntcodeError::Device { message: error.to_string() })?;
+
+            if read == 0 {
+                return Err(IntcodeError::InputUnavailable);
+            }
+
+            let token = line.trim();
+            if token.is_empty() {
+                continue;
+            }
+
+            return token.parse::<i64>().map_err(|_| IntcodeError::Parse {
+                column: line.find(token).unwrap_or(0) + 1,
+                token: token.to_string(),
+            });
+        }
+    }
+}
+
+/// Collects every output value in order.
+impl OutputDevice for Vec<i64> {
+    fn write(&mut self, value: i64) -> Result<(), IntcodeError> {
+        self.push(value);
+
+        Ok(())
+    }
+}
+
+/// Forwards output to the receiving half, failing once it has hung up.
+impl OutputDevice for Sender<i64> {
+    fn write(&mut self, value: i64) -> Result<(), IntcodeError> {
+        self.send(value).map_err(|_| IntcodeError::OutputUnavailable)
+    }
+}
+
+/// Prints every output value on its own line to stderr.
+pub struct StderrOutput;
+
+impl OutputDevice for StderrOutput {
+    fn write(&mut self, value: i64) -> Result<(), IntcodeError> {
+        eprintln!("{}", value);
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::sync::mpsc;
+
+    #[test]
+    fn it_reads_values_from_lines() {
+        let mut input = LineInput::new("5\n\n -3 \n".as_bytes());
+
+        assert_eq!(input.read(), Ok(5));
+        assert_eq!(input.read(), Ok(-3));
+        assert_eq!(input.read(), Err(IntcodeError::InputUnavailable));
+    }
+
+    #[test]
+    fn it_passes_values_through_channels() {
+        let (mut sender, mut receiver) = mpsc::channel();
+
+        sender.write(7).unwrap();
+        assert_eq!(receiver.read(), Ok(7));
+
+        drop(sender);
+        assert_eq!(receiver.read(), Err(IntcodeError::InputUnavailable));
+    }
+}
@@ -0,0 +1,32 @@
+//! Intcode virtual machine shared by the puzzles that run intcode programs.
+
+pub mod amplifier;
+pub mod ascii;
+pub mod asm;
+#[cfg(feature = "async")]
+pub mod asynchronous;
+pub mod cfg;
+pub mod debugger;
+pub mod device;
+#[cfg(test)]
+mod differential;
+pub mod disasm;
+mod error;
+pub mod handler;
+pub mod history;
+mod instruction;
+pub mod io;
+pub mod limits;
+pub mod loops;
+pub mod network;
+pub mod profile;
+mod program;
+pub mod search;
+pub mod snapshot;
+pub mod symbolic;
+pub mod trace;
+
+pub use error::{DecodeError, IntcodeError};
+pub use io::{InputDevice, OutputDevice};
+pub use instruction::{Instruction, Opcode, Parameter, ParameterMode};
+pub use program::{parse_program_into_instructions, Execution, MemoryWrite, Program, Status};
@@ -0,0 +1,58 @@
+//! Resource limits for running programs nobody has vetted.
+//!
+//! Every limit is off by default. Once set with `Program::set_limits`, a
+//! program that exceeds one fails with its own `IntcodeError` variant
+//! instead of looping forever or growing memory without bound:
+//!
+//! ```
+//! use intcode::limits::Limits;
+//! use intcode::{IntcodeError, Program};
+//!
+//! let mut program = Program::from(vec![1105, 1, 0]);
+//! program.set_limits(Limits::new().steps(1000));
+//!
+//! assert_eq!(program.run(), Err(IntcodeError::StepLimitExceeded { limit: 1000 }));
+//! ```
+
+use std::time::Duration;
+
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub struct Limits {
+    pub steps: Option<u64>,
+    pub memory: Option<usize>,
+    pub outputs: Option<u64>,
+    pub timeout: Option<Duration>,
+}
+
+impl Limits {
+    /// No limits at all.
+    pub fn new() -> Limits {
+        Limits::default()
+    }
+
+    /// Fail before executing more than `steps` instructions.
+    pub fn steps(mut self, steps: u64) -> Limits {
+        self.steps = Some(steps);
+        self
+    }
+
+    /// Fail when memory would grow beyond `cells` cells. Programs loaded
+    /// larger than that may still write within their image.
+    pub fn memory(mut self, cells: usize) -> Limits {
+        self.memory = Some(cells);
+        self
+    }
+
+    /// Fail before producing more than `outputs` values.
+    pub fn outputs(mut self, outputs: u64) -> Limits {
+        self.outputs = Some(outputs);
+        self
+    }
+
+    /// Fail once the program has been executing for longer than `timeout`,
+    /// counted from the first instruction executed after setting limits.
+    pub fn timeout(mut self, timeout: Duration) -> Limits {
+        self.timeout = Some(timeout);
+        self
+    }
+}
@@ -0,0 +1,229 @@
+//! Opt-in detection of programs that will never halt.
+//!
+//! After every taken jump the machine state, made up of the index, the
+//! relative base, the pending input and memory, is compared with a saved
+//! checkpoint. A program that comes back to a saved state without
+//! consuming input in between repeats itself forever, so execution fails
+//! with `IntcodeError::InfiniteLoop`. Checkpoints are taken after 1, 2, 4,
+//! ... jumps (Brent's cycle detection), so a loop is found within a few
+//! laps of it and only one checkpoint is ever kept.
+//!
+//! Memory enters the state through a hash updated on every write, which
+//! keeps each comparison cheap whatever the size of memory.
+//! `LoopDetection::Exact` confirms a matching hash against a full copy of
+//! memory taken with the checkpoint. `LoopDetection::Heuristic` trusts the
+//! hash and never copies memory, at the price of reporting a loop on the
+//! vanishingly rare hash collision.
+//!
+//! Values read from mapped devices are not part of the state, so a program
+//! polling a device would look like it repeats itself. Detection in either
+//! mode stays off while devices are mapped.
+
+use std::collections::VecDeque;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum LoopDetection {
+    Exact,
+    Heuristic,
+}
+
+/// Hash of a single memory cell. Zero cells hash to zero, so memory that
+/// grows with zeroes keeps its hash.
+fn cell(address: usize, value: i64) -> u64 {
+    if value == 0 {
+        return 0;
+    }
+
+    mix((address as u64).wrapping_mul(0x9e37_79b9_7f4a_7c15) ^ value as u64)
+}
+
+/// The splitmix64 finalizer.
+fn mix(mut value: u64) -> u64 {
+    value = (value ^ (value >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+    value = (value ^ (value >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+    value ^ (value >> 31)
+}
+
+fn trimmed(memory: &[i64]) -> &[i64] {
+    let len = memory.iter().rposition(|value| *value != 0).map_or(0, |last| last + 1);
+
+    &memory[..len]
+}
+
+#[derive(Debug, Clone)]
+struct Checkpoint {
+    hash: u64,
+    index: usize,
+    relative_base: i64,
+    input: Vec<i64>,
+    /// Memory without trailing zeroes, kept by exact detection only.
+    memory: Option<Vec<i64>>,
+    step: u64,
+}
+
+/// A loop found by the detector, in instructions and addresses.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(crate) struct Loop {
+    pub(crate) start: usize,
+    pub(crate) end: usize,
+    pub(crate) period: u64,
+}
+
+#[derive(Debug, Clone)]
+pub(crate) struct Detector {
+    mode: LoopDetection,
+    memory_hash: u64,
+    step: u64,
+    checkpoint: Option<Checkpoint>,
+    /// Jumps until the next checkpoint.
+    remaining: u64,
+    power: u64,
+    /// Addresses executed since the checkpoint.
+    start: usize,
+    end: usize,
+}
+
+impl Detector {
+    pub(crate) fn new(mode: LoopDetection, memory: &[i64]) -> Detector {
+        Detector {
+            mode,
+            memory_hash: memory.iter().enumerate().fold(0, |hash, (address, value)| hash ^ cell(address, *value)),
+            step: 0,
+            checkpoint: None,
+            remaining: 1,
+            power: 1,
+            start: usize::MAX,
+            end: 0,
+        }
+    }
+
+    pub(crate) fn mode(&self) -> LoopDetection {
+        self.mode
+    }
+
+    pub(crate) fn written(&mut self, address: usize, old: i64, new: i64) {
+        self.memory_hash ^= cell(address, old) ^ cell(address, new);
+    }
+
+    /// Forget the checkpoint, for when state came from outside the program.
+    pub(crate) fn reset(&mut self) {
+        self.checkpoint = None;
+        self.remaining = 1;
+        self.power = 1;
+        self.start = usize::MAX;
+        self.end = 0;
+    }
+
+    /// Count an executed instruction of `size` cells at `address`.
+    pub(crate) fn executed(&mut self, address: usize, size: usize) {
+        self.step += 1;
+        self.start = self.start.min(address);
+        self.end = self.end.max(address + size);
+    }
+
+    /// Compare the state after a taken jump with the checkpoint.
+    pub(crate) fn jumped(
+        &mut self,
+        index: usize,
+        relative_base: i64,
+        input: &VecDeque<i64>,
+        memory: &[i64],
+    ) -> Option<Loop> {
+        let mut hash = mix(self.memory_hash ^ mix(index as u64 ^ mix(relative_base as u64)));
+        for value in input.iter() {
+            hash = mix(hash ^ *value as u64);
+        }
+
+        if let Some(checkpoint) = &self.checkpoint {
+            let repeated = checkpoint.hash == hash
+                && checkpoint.index == index
+                && checkpoint.relative_base == relative_base
+                && checkpoint.input.iter().eq(input.iter())
+                && checkpoint.memory.as_deref().is_none_or(|saved| saved == trimmed(memory));
+
+            if repeated {
+                return Some(Loop { start: self.start, end: self.end, period: self.step - checkpoint.step });
+            }
+        }
+
+        self.remaining -= 1;
+        if self.remaining == 0 {
+            self.checkpoint = Some(Checkpoint {
+                hash,
+                index,
+                relative_base,
+                input: input.iter().copied().collect(),
+                memory: match self.mode {
+                    LoopDetection::Exact => Some(trimmed(memory).to_vec()),
+                    LoopDetection::Heuristic => None,
+                },
+                step: self.step,
+            });
+            self.power *= 2;
+            self.remaining = self.power;
+            self.start = usize::MAX;
+            self.end = 0;
+        }
+
+        None
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::device::CycleCounter;
+    use crate::error::IntcodeError;
+    use crate::program::{Program, Status};
+    use std::sync::Arc;
+
+    #[test]
+    fn it_finds_loops_in_both_modes() {
+        // Counts to 3, then spins between 11 and 17 for good.
+        let source = vec![1001, 30, 1, 30, 1007, 30, 3, 31, 1005, 31, 0, 1101, 0, 0, 32, 1105, 1, 11, 99];
+
+        for mode in [LoopDetection::Exact, LoopDetection::Heuristic].iter() {
+            let mut program = Program::from(source.clone());
+            program.detect_loops(*mode);
+
+            assert_eq!(program.run(), Err(IntcodeError::InfiniteLoop { start: 11, end: 18, period: 2 }));
+            assert_eq!(program.index, 11);
+        }
+    }
+
+    #[test]
+    fn it_lets_programs_that_change_state_run() {
+        // Counts down from 2000 in a loop before halting.
+        let mut program = Program::from(vec![1001, 9, -1, 9, 1005, 9, 0, 99, 0, 2000]);
+        program.detect_loops(LoopDetection::Exact);
+
+        assert_eq!(program.run(), Ok(Status::Halted));
+    }
+
+    #[test]
+    fn it_leaves_programs_polling_devices_alone() {
+        // Waits for the cycle counter to reach 100.
+        let source = vec![1007, 50, 100, 51, 1005, 51, 0, 99];
+
+        for mode in [LoopDetection::Exact, LoopDetection::Heuristic] {
+            let mut program = Program::from(source.clone());
+            program.map_device(50, Arc::new(CycleCounter::new())).unwrap();
+            program.detect_loops(mode);
+            assert_eq!(program.run(), Ok(Status::Halted));
+        }
+    }
+
+    #[test]
+    fn it_starts_over_after_reading_input() {
+        // Waits for a non-zero value, the same way every time.
+        let mut program = Program::from(vec![3, 9, 1006, 9, 0, 4, 9, 99, 0, 0]);
+        program.detect_loops(LoopDetection::Exact);
+        for _ in 0..10 {
+            program.push_input(0);
+            assert_eq!(program.run(), Ok(Status::NeedsInput));
+        }
+
+        program.push_input(7);
+        assert_eq!(program.run(), Ok(Status::Output(7)));
+    }
+}
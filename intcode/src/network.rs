@@ -0,0 +1,344 @@
+//! A network of intcode computers exchanging packets.
+//!
+//! Every node boots with its address as the first input. Nodes send packets
+//! by emitting three values: destination address, X and Y. Incoming packets
+//! are queued per node and handed over as X followed by Y; a node asking for
+//! input with an empty queue receives `-1` instead of blocking.
+//!
+//! An optional NAT listens on its own address, remembers the last packet it
+//! received and sends it to node 0 once the network has been idle for a
+//! configurable number of ticks.
+//!
+//! A node may execute a bounded number of instructions between two inputs or
+//! outputs, so one stuck in a loop fails the tick instead of hanging it.
+
+use std::collections::VecDeque;
+
+use crate::error::IntcodeError;
+use crate::program::{Program, Status};
+
+/// Instructions a node may execute between inputs or outputs by default.
+const DEFAULT_STEP_BUDGET: u64 = 1_000_000;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Packet {
+    pub source: i64,
+    pub destination: i64,
+    pub x: i64,
+    pub y: i64,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Event {
+    /// A packet was queued for a node.
+    Sent(Packet),
+    /// The NAT stored a packet, replacing the previous one.
+    NatReceived(Packet),
+    /// The network was idle and the NAT sent its last packet to node 0.
+    NatDelivered(Packet),
+    /// A packet addressed to a node that does not exist.
+    Dropped(Packet),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Nat {
+    pub address: i64,
+    /// Consecutive idle ticks required before the NAT wakes node 0.
+    pub idle_ticks: usize,
+    last: Option<Packet>,
+}
+
+impl Nat {
+    pub fn new(address: i64, idle_ticks: usize) -> Nat {
+        Nat {
+            address,
+            idle_ticks,
+            last: None,
+        }
+    }
+
+    /// Last packet the NAT received.
+    pub fn last(&self) -> Option<Packet> {
+        self.last
+    }
+}
+
+impl Default for Nat {
+    fn default() -> Nat {
+        Nat::new(255, 2)
+    }
+}
+
+#[derive(Debug, Clone)]
+struct Node {
+    program: Program,
+    outbox: Vec<i64>,
+    queue: VecDeque<(i64, i64)>,
+    idle: bool,
+    halted: bool,
+}
+
+#[derive(Debug, Clone)]
+pub struct Network {
+    nodes: Vec<Node>,
+    nat: Option<Nat>,
+    idle_ticks: usize,
+    ticks: u64,
+    step_budget: u64,
+}
+
+impl Network {
+    /// Boot `size` copies of `program`, addressed from 0.
+    pub fn new(program: &Program, size: usize) -> Network {
+        let nodes = (0..size)
+            .map(|address| {
+                let mut program = program.clone();
+                program.push_input(address as i64);
+
+                Node {
+                    program,
+                    outbox: vec![],
+                    queue: VecDeque::new(),
+                    idle: false,
+                    halted: false,
+                }
+            })
+            .collect();
+
+        Network {
+            nodes,
+            nat: None,
+            idle_ticks: 0,
+            ticks: 0,
+            step_budget: DEFAULT_STEP_BUDGET,
+        }
+    }
+
+    pub fn with_nat(mut self, nat: Nat) -> Network {
+        self.nat = Some(nat);
+        self
+    }
+
+    /// Fail a tick with `IntcodeError::Unresponsive` when a node executes
+    /// `steps` instructions without input or output.
+    pub fn with_step_budget(mut self, steps: u64) -> Network {
+        self.step_budget = steps;
+        self
+    }
+
+    pub fn nat(&self) -> Option<&Nat> {
+        self.nat.as_ref()
+    }
+
+    /// Number of ticks run so far.
+    pub fn ticks(&self) -> u64 {
+        self.ticks
+    }
+
+    /// True when every running node is waiting on an empty queue.
+    pub fn is_idle(&self) -> bool {
+        self.nodes.iter().all(|node| node.halted || (node.idle && node.queue.is_empty()))
+    }
+
+    fn route(&mut self, packet: Packet) -> Event {
+        if let Some(nat) = self.nat.as_mut() {
+            if packet.destination == nat.address {
+                nat.last = Some(packet);
+                return Event::NatReceived(packet);
+            }
+        }
+
+        match self.nodes.get_mut(packet.destination as usize) {
+            Some(node) if packet.destination >= 0 => {
+                node.queue.push_back((packet.x, packet.y));
+                Event::Sent(packet)
+            },
+            _ => Event::Dropped(packet),
+        }
+    }
+
+    /// Run every node until it asks for input again, routing the packets it
+    /// sends, then hand each node its next packet or `-1`.
+    pub fn tick(&mut self) -> Result<Vec<Event>, IntcodeError> {
+        let mut events = vec![];
+
+        for address in 0..self.nodes.len() {
+            let mut sent = false;
+            let mut steps = 0;
+
+            loop {
+                let node = &mut self.nodes[address];
+                if node.halted {
+                    break;
+                }
+
+                let status = match node.program.step()? {
+                    Some(status) => status,
+                    None => {
+                        steps += 1;
+                        if steps >= self.step_budget {
+                            return Err(IntcodeError::Unresponsive { node: address, steps });
+                        }
+                        continue;
+                    },
+                };
+                steps = 0;
+
+                match status {
+                    Status::Output(value) => node.outbox.push(value),
+                    Status::NeedsInput => break,
+                    Status::Halted => node.halted = true,
+                }
+
+                if node.outbox.len() == 3 {
+                    let packet = Packet {
+                        source: address as i64,
+                        destination: node.outbox[0],
+                        x: node.outbox[1],
+                        y: node.outbox[2],
+                    };
+                    node.outbox.clear();
+                    sent = true;
+
+                    events.push(self.route(packet));
+                }
+            }
+
+            let node = &mut self.nodes[address];
+            match node.queue.pop_front() {
+                Some((x, y)) => {
+                    node.program.push_input(x);
+                    node.program.push_input(y);
+                    node.idle = false;
+                },
+                None => {
+                    node.program.push_input(-1);
+                    node.idle = !sent;
+                },
+            }
+        }
+
+        self.ticks += 1;
+        self.idle_ticks = if self.is_idle() { self.idle_ticks + 1 } else { 0 };
+
+        if let Some(nat) = self.nat.as_ref() {
+            if let (true, Some(packet)) = (self.idle_ticks >= nat.idle_ticks, nat.last) {
+                let packet = Packet {
+                    source: nat.address,
+                    destination: 0,
+                    ..packet
+                };
+                self.idle_ticks = 0;
+
+                if let Some(node) = self.nodes.get_mut(0) {
+                    node.queue.push_back((packet.x, packet.y));
+                    node.idle = false;
+                }
+                events.push(Event::NatDelivered(packet));
+            }
+        }
+
+        Ok(events)
+    }
+
+    /// Tick until `predicate` accepts an event, giving up after `max_ticks`.
+    pub fn run_until<P>(&mut self, max_ticks: u64, mut predicate: P) -> Result<Option<Event>, IntcodeError>
+    where
+        P: FnMut(&Event) -> bool,
+    {
+        for _ in 0..max_ticks {
+            if let Some(event) = self.tick()?.into_iter().find(|event| predicate(event)) {
+                return Ok(Some(event));
+            }
+        }
+
+        Ok(None)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::asm::assemble;
+
+    /// Node 0 kicks off a packet that every node forwards to the next one;
+    /// the last node forwards it to the NAT.
+    const RELAY: &str = "
+                in [address]
+                jnz [address], #receive
+                out #1
+                out #7
+                out #42
+        receive:
+                in [x]
+                eq [x], #-1, [flag]
+                jnz [flag], #receive
+                in [y]
+                add [address], #1, [destination]
+                eq [destination], #3, [flag]
+                jz [flag], #send
+                add #255, #0, [destination]
+        send:   out [destination]
+                out [x]
+                out [y]
+                jz #0, #receive
+        address:     .data 0
+        x:           .data 0
+        y:           .data 0
+        destination: .data 0
+        flag:        .data 0
+    ";
+
+    fn relay() -> Program {
+        Program::from(assemble(RELAY).unwrap())
+    }
+
+    #[test]
+    fn it_routes_packets_between_nodes() {
+        let mut network = Network::new(&relay(), 3);
+        let mut events = vec![];
+        for _ in 0..4 {
+            events.extend(network.tick().unwrap());
+        }
+
+        assert_eq!(events, vec![
+            Event::Sent(Packet { source: 0, destination: 1, x: 7, y: 42 }),
+            Event::Sent(Packet { source: 1, destination: 2, x: 7, y: 42 }),
+            Event::Dropped(Packet { source: 2, destination: 255, x: 7, y: 42 }),
+        ]);
+    }
+
+    #[test]
+    fn it_reports_nodes_that_stop_talking() {
+        // Node 1 spins forever after reading its address.
+        let program = Program::from(assemble("
+                    in [address]
+                    jnz [address], #spin
+                    in [address]
+                    jz #0, #0
+            spin:   jz #0, #spin
+            address: .data 0
+        ").unwrap());
+        let mut network = Network::new(&program, 2).with_step_budget(100);
+
+        assert_eq!(network.tick(), Err(IntcodeError::Unresponsive { node: 1, steps: 100 }));
+    }
+
+    #[test]
+    fn it_wakes_the_network_through_the_nat() {
+        let mut network = Network::new(&relay(), 3).with_nat(Nat::default());
+
+        let received = network.run_until(100, |event| matches!(event, Event::NatReceived(_)));
+        assert_eq!(received, Ok(Some(Event::NatReceived(Packet { source: 2, destination: 255, x: 7, y: 42 }))));
+
+        let delivered = network.run_until(100, |event| matches!(event, Event::NatDelivered(_)));
+        assert_eq!(delivered, Ok(Some(Event::NatDelivered(Packet { source: 255, destination: 0, x: 7, y: 42 }))));
+        assert!(!network.is_idle());
+
+        let forwarded = network.run_until(100, |event| match event {
+            Event::Sent(packet) => packet.source == 0,
+            _ => false,
+        });
+        assert_eq!(forwarded, Ok(Some(Event::Sent(Packet { source: 0, destination: 1, x: 7, y: 42 }))));
+    }
+}
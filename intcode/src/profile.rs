@@ -0,0 +1,278 @@
+//! Execution profile of a program, gathered through `Program::run_traced`.
+//!
+//! Counts how often each opcode, each instruction address and each edge out
+//! of a conditional jump executes. The folded stacks written by
+//! `Profiler::folded` load straight into flamegraph tools:
+//!
+//! ```text
+//! entry 0;0 jnz 1
+//! entry 3;3 add 2
+//! entry 3;7 jnz 2
+//! ```
+//!
+//! Intcode has no call stack, so every instruction is filed under the jump
+//! target that entered the code it runs in; hot loops show up as one wide
+//! frame each.
+
+use std::collections::HashMap;
+use std::fmt::Write;
+use std::time::{Duration, Instant};
+
+use crate::error::IntcodeError;
+use crate::instruction::Opcode;
+use crate::program::{Execution, Status};
+use crate::trace::Tracer;
+
+/// Instruction address and the number of times it executed.
+#[derive(Debug, Clone, PartialEq)]
+pub struct HotSpot {
+    pub address: usize,
+    /// The opcode found at the address. Self-modifying programs can list
+    /// an address once per opcode it held.
+    pub opcode: Opcode,
+    pub count: u64,
+}
+
+/// A conditional jump at `from` continuing at `to`, whether it was taken or
+/// fell through.
+#[derive(Debug, Clone, PartialEq)]
+pub struct JumpEdge {
+    pub from: usize,
+    pub to: usize,
+    pub count: u64,
+}
+
+#[derive(Debug)]
+pub struct Profiler {
+    steps: u64,
+    /// Counts by opcode number.
+    opcodes: Vec<u64>,
+    /// Counts keyed by entry, address and opcode.
+    stacks: HashMap<(usize, usize, Opcode), u64>,
+    jumps: HashMap<(usize, usize), u64>,
+    entry: Option<usize>,
+    started: Instant,
+    elapsed: Option<Duration>,
+}
+
+/// Mnemonic of built-in opcodes, `op` and the number for custom ones.
+fn name(opcode: Opcode) -> String {
+    match opcode {
+        Opcode::Custom(code) => format!("op{}", code),
+        opcode => opcode.mnemonic().to_string(),
+    }
+}
+
+fn percent(count: u64, total: u64) -> f64 {
+    if total == 0 {
+        0.0
+    } else {
+        count as f64 * 100.0 / total as f64
+    }
+}
+
+impl Profiler {
+    /// Start a profile; the wall clock runs from here.
+    pub fn new() -> Profiler {
+        Profiler {
+            steps: 0,
+            opcodes: vec![0; 100],
+            stacks: HashMap::new(),
+            jumps: HashMap::new(),
+            entry: None,
+            started: Instant::now(),
+            elapsed: None,
+        }
+    }
+
+    /// Stop the wall clock. Recording a halt stops it as well.
+    pub fn stop(&mut self) {
+        let started = self.started;
+        self.elapsed.get_or_insert_with(|| started.elapsed());
+    }
+
+    pub fn steps(&self) -> u64 {
+        self.steps
+    }
+
+    pub fn elapsed(&self) -> Duration {
+        self.elapsed.unwrap_or_else(|| self.started.elapsed())
+    }
+
+    pub fn opcode_count(&self, opcode: Opcode) -> u64 {
+        self.opcodes[opcode.code() as usize]
+    }
+
+    /// Instructions executed at each address, most frequent first.
+    pub fn hot_spots(&self) -> Vec<HotSpot> {
+        let mut counts: HashMap<(usize, Opcode), u64> = HashMap::new();
+        for ((_, address, opcode), count) in self.stacks.iter() {
+            *counts.entry((*address, *opcode)).or_insert(0) += count;
+        }
+
+        let mut hot_spots: Vec<HotSpot> = counts.into_iter()
+            .map(|((address, opcode), count)| HotSpot { address, opcode, count })
+            .collect();
+        hot_spots.sort_by_key(|spot| (std::cmp::Reverse(spot.count), spot.address, spot.opcode.code()));
+
+        hot_spots
+    }
+
+    /// Edges out of conditional jumps, most frequent first.
+    pub fn jump_edges(&self) -> Vec<JumpEdge> {
+        let mut edges: Vec<JumpEdge> = self.jumps.iter()
+            .map(|((from, to), count)| JumpEdge { from: *from, to: *to, count: *count })
+            .collect();
+        edges.sort_by_key(|edge| (std::cmp::Reverse(edge.count), edge.from, edge.to));
+
+        edges
+    }
+
+    /// Hot-spot tables for opcodes, addresses and jump edges, listing at
+    /// most `limit` addresses and edges.
+    pub fn report(&self, limit: usize) -> String {
+        let mut report = String::new();
+        writeln!(report, "{} steps in {:?}", self.steps, self.elapsed()).unwrap();
+
+        writeln!(report, "\nopcode          count        %").unwrap();
+        let mut opcodes: Vec<(Opcode, u64)> = self.opcodes.iter()
+            .enumerate()
+            .filter(|(_, count)| **count > 0)
+            .map(|(code, count)| (Opcode::from_code(code as u32), *count))
+            .collect();
+        opcodes.sort_by_key(|(_, count)| std::cmp::Reverse(*count));
+        for (opcode, count) in opcodes {
+            writeln!(report, "{:<6} {:>12} {:>7.2}%", name(opcode), count, percent(count, self.steps)).unwrap();
+        }
+
+        writeln!(report, "\naddress  opcode        count        %").unwrap();
+        for spot in self.hot_spots().iter().take(limit) {
+            writeln!(
+                report,
+                "{:>7}  {:<6} {:>12} {:>7.2}%",
+                spot.address,
+                name(spot.opcode),
+                spot.count,
+                percent(spot.count, self.steps),
+            ).unwrap();
+        }
+
+        writeln!(report, "\njump                   count        %").unwrap();
+        for edge in self.jump_edges().iter().take(limit) {
+            writeln!(
+                report,
+                "{:>7} -> {:<7} {:>12} {:>7.2}%",
+                edge.from,
+                edge.to,
+                edge.count,
+                percent(edge.count, self.steps),
+            ).unwrap();
+        }
+
+        report
+    }
+
+    /// One folded stack per line, `entry E;ADDRESS MNEMONIC COUNT`, in
+    /// address order.
+    pub fn folded(&self) -> String {
+        let mut stacks: Vec<_> = self.stacks.iter().collect();
+        stacks.sort_by_key(|((entry, address, opcode), _)| (*entry, *address, opcode.code()));
+
+        stacks.into_iter()
+            .map(|((entry, address, opcode), count)| {
+                format!("entry {};{} {} {}\n", entry, address, name(*opcode), count)
+            })
+            .collect()
+    }
+}
+
+impl Default for Profiler {
+    fn default() -> Profiler {
+        Profiler::new()
+    }
+}
+
+impl Tracer for Profiler {
+    fn record(&mut self, execution: &Execution) -> Result<(), IntcodeError> {
+        let address = execution.address;
+        let entry = *self.entry.get_or_insert(address);
+
+        self.steps += 1;
+        self.opcodes[execution.opcode.code() as usize] += 1;
+        *self.stacks.entry((entry, address, execution.opcode)).or_insert(0) += 1;
+
+        match execution.opcode {
+            Opcode::JumpIfTrue | Opcode::JumpIfFalse => {
+                let parameters = execution.parameters();
+                let taken = (parameters[0] != 0) == (execution.opcode == Opcode::JumpIfTrue);
+                let to = if taken { parameters[1] as usize } else { address + execution.parameters().len() + 1 };
+
+                *self.jumps.entry((address, to)).or_insert(0) += 1;
+                if taken {
+                    self.entry = Some(to);
+                }
+            },
+            Opcode::Exit if execution.status == Some(Status::Halted) => self.stop(),
+            _ => {},
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::program::Program;
+    use std::collections::VecDeque;
+
+    /// Jumps straight into a loop counting down from 2.
+    fn profile() -> Profiler {
+        let mut program = Program::from(vec![1105, 1, 3, 1001, 11, -1, 11, 1005, 11, 3, 99, 2]);
+        let mut profiler = Profiler::new();
+        program.run_traced(&mut VecDeque::new(), &mut vec![], &mut profiler).unwrap();
+
+        profiler
+    }
+
+    #[test]
+    fn it_counts_opcodes_addresses_and_jump_edges() {
+        let profiler = profile();
+
+        assert_eq!(profiler.steps(), 6);
+        assert_eq!(profiler.opcode_count(Opcode::JumpIfTrue), 3);
+        assert_eq!(profiler.opcode_count(Opcode::Addition), 2);
+        assert_eq!(profiler.opcode_count(Opcode::Multiplication), 0);
+        assert_eq!(profiler.hot_spots(), vec![
+            HotSpot { address: 3, opcode: Opcode::Addition, count: 2 },
+            HotSpot { address: 7, opcode: Opcode::JumpIfTrue, count: 2 },
+            HotSpot { address: 0, opcode: Opcode::JumpIfTrue, count: 1 },
+            HotSpot { address: 10, opcode: Opcode::Exit, count: 1 },
+        ]);
+        assert_eq!(profiler.jump_edges(), vec![
+            JumpEdge { from: 0, to: 3, count: 1 },
+            JumpEdge { from: 7, to: 3, count: 1 },
+            JumpEdge { from: 7, to: 10, count: 1 },
+        ]);
+    }
+
+    #[test]
+    fn it_files_instructions_under_the_jump_that_entered_them() {
+        assert_eq!(profile().folded(), "\
+entry 0;0 jnz 1
+entry 3;3 add 2
+entry 3;7 jnz 2
+entry 3;10 hlt 1
+");
+    }
+
+    #[test]
+    fn it_stops_the_clock_when_the_program_halts() {
+        let profiler = profile();
+        let elapsed = profiler.elapsed();
+
+        assert_eq!(profiler.elapsed(), elapsed);
+        assert!(profiler.report(2).starts_with(&format!("6 steps in {:?}\n", elapsed)));
+        assert!(profiler.report(2).contains("      3  add               2   33.33%\n"));
+    }
+}
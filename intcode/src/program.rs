@@ -0,0 +1,847 @@
+use crate::device::Device;
+use crate::error::{DecodeError, IntcodeError};
+use crate::handler::{Context, Effect, Handler, InstructionHandler, InstructionSet, MAX_WRITES};
+use crate::io::{InputDevice, OutputDevice};
+use crate::limits::Limits;
+use crate::loops::{Detector, LoopDetection};
+use crate::trace::Tracer;
+use crate::instruction::{Instruction, Opcode, Parameter, ParameterMode};
+
+use std::collections::VecDeque;
+use std::fmt;
+use std::ops::Range;
+use std::sync::Arc;
+use std::time::Instant;
+
+/// Instructions executed between two looks at the clock when a timeout is set.
+const TIMEOUT_INTERVAL: u64 = 256;
+
+/// Reason `Program::run` handed control back to the caller.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Status {
+    /// Paused on `Opcode::Input` with an empty input queue. Push a value and
+    /// run again to resume.
+    NeedsInput,
+    /// `Opcode::Output` emitted a value; execution resumes after it.
+    Output(i64),
+    /// Reached `Opcode::Exit`. Running again halts immediately.
+    Halted,
+}
+
+/// A memory cell changed by an executed instruction. Writes to a device
+/// record the memory underneath it as `old`, so recording never reads from
+/// the device.
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub struct MemoryWrite {
+    pub address: usize,
+    pub old: i64,
+    pub new: i64,
+}
+
+/// How much of its limits a program has used, so undoing instructions can
+/// give it back.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(crate) struct Usage {
+    executed: u64,
+    outputs: u64,
+    started: Option<Instant>,
+}
+
+/// Record of a single instruction executed by `Program::execute`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Execution {
+    pub address: usize,
+    /// The raw opcode word, including parameter modes.
+    pub word: i64,
+    pub opcode: Opcode,
+    parameters: [i64; 3],
+    arity: usize,
+    writes: [MemoryWrite; MAX_WRITES],
+    written: usize,
+    pub input: Option<i64>,
+    pub output: Option<i64>,
+    pub status: Option<Status>,
+}
+
+impl Execution {
+    /// Parameter values after resolving their modes. The parameters an
+    /// instruction writes to resolve to destination addresses.
+    pub fn parameters(&self) -> &[i64] {
+        &self.parameters[..self.arity]
+    }
+
+    /// Memory cells the instruction changed, in the order it wrote them.
+    pub fn writes(&self) -> &[MemoryWrite] {
+        &self.writes[..self.written]
+    }
+
+    pub(crate) fn record_write(&mut self, write: MemoryWrite) {
+        self.writes[self.written] = write;
+        self.written += 1;
+    }
+}
+
+/// A device and the addresses it occupies.
+#[derive(Clone)]
+struct Mapping {
+    addresses: Range<usize>,
+    device: Arc<dyn Device>,
+}
+
+impl fmt::Debug for Mapping {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Mapping({:?})", self.addresses)
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Program {
+    data: Vec<i64>,
+    pub index: usize,
+    pub relative_base: i64,
+    input: VecDeque<i64>,
+    /// Instructions decoded so far, by address. A write to an address drops
+    /// its entry, so self-modifying programs decode the new word.
+    decoded: Vec<Option<Instruction>>,
+    cache: bool,
+    instructions: Arc<InstructionSet>,
+    devices: Vec<Mapping>,
+    loops: Option<Detector>,
+    limits: Limits,
+    /// Instructions executed and values output since limits were set.
+    executed: u64,
+    outputs: u64,
+    started: Option<Instant>,
+}
+
+impl PartialEq for Program {
+    fn eq(&self, other: &Program) -> bool {
+        self.data == other.data
+            && self.index == other.index
+            && self.relative_base == other.relative_base
+            && self.input == other.input
+    }
+}
+
+pub(crate) fn to_address(value: i64) -> Result<usize, IntcodeError> {
+    if value < 0 {
+        Err(IntcodeError::NegativeAddress { value })
+    } else {
+        Ok(value as usize)
+    }
+}
+
+impl Program {
+    /// Read a memory cell. Cells beyond the loaded image read as zero.
+    pub fn value(&self, index: usize) -> Result<i64, IntcodeError> {
+        if let Some(mapping) = self.device_at(index) {
+            return mapping.device.read(index - mapping.addresses.start);
+        }
+
+        Ok(self.data.get(index).copied().unwrap_or(0))
+    }
+
+    /// Write a memory cell, growing memory with zeroes when writing beyond
+    /// the end of it.
+    pub fn write(&mut self, index: usize, input: i64) -> Result<(), IntcodeError> {
+        if let Some(mapping) = self.device_at(index) {
+            return mapping.device.write(index - mapping.addresses.start, input);
+        }
+
+        if index >= self.data.len() {
+            if let Some(limit) = self.limits.memory {
+                if index >= limit {
+                    return Err(IntcodeError::MemoryLimitExceeded { address: index, limit });
+                }
+            }
+
+            let additional = index - self.data.len() + 1;
+            self.data.try_reserve(additional)
+                .map_err(|_| IntcodeError::OutOfBoundsWrite { address: index })?;
+            self.data.resize(index + 1, 0);
+        }
+
+        if let Some(detector) = self.loops.as_mut() {
+            detector.written(index, self.data[index], input);
+        }
+        self.data[index] = input;
+
+        if let Some(decoded) = self.decoded.get_mut(index) {
+            *decoded = None;
+        }
+
+        Ok(())
+    }
+
+    /// Map `device` to the addresses starting at `start`. Reads and writes
+    /// there go to the device; memory underneath is left alone.
+    ///
+    /// Fails with `IntcodeError::InvalidDeviceMapping` if the device overlaps
+    /// one mapped earlier or runs past the last address.
+    pub fn map_device<D: Device + 'static>(&mut self, start: usize, device: Arc<D>) -> Result<(), IntcodeError> {
+        let invalid = IntcodeError::InvalidDeviceMapping { start, len: device.len() };
+        let addresses = start..start.checked_add(device.len()).ok_or_else(|| invalid.clone())?;
+        if self.devices.iter().any(|mapping| {
+            mapping.addresses.start < addresses.end && addresses.start < mapping.addresses.end
+        }) {
+            return Err(invalid);
+        }
+
+        self.devices.push(Mapping { addresses, device });
+        self.decoded.clear();
+
+        Ok(())
+    }
+
+    fn device_at(&self, index: usize) -> Option<&Mapping> {
+        self.devices.iter().find(|mapping| mapping.addresses.contains(&index))
+    }
+
+    /// Count an executed instruction.
+    fn retire(&mut self) {
+        self.executed += 1;
+        for mapping in self.devices.iter() {
+            mapping.device.tick();
+        }
+    }
+
+    /// Queue a value for the next `Opcode::Input`.
+    pub fn push_input(&mut self, value: i64) {
+        self.input.push_back(value);
+    }
+
+    /// Consume the value at the front of the input queue.
+    pub(crate) fn take_input(&mut self) -> Option<i64> {
+        self.input.pop_front()
+    }
+
+    /// Put a consumed value back at the front of the input queue.
+    pub(crate) fn unread_input(&mut self, value: i64) {
+        self.input.push_front(value);
+    }
+
+    /// Put back a memory cell an undone instruction wrote. Unlike `write`
+    /// this never reaches a mapped device: device writes left memory alone,
+    /// and devices cannot be rewound.
+    pub(crate) fn restore(&mut self, index: usize, value: i64) {
+        if self.device_at(index).is_some() {
+            return;
+        }
+
+        if let Some(cell) = self.data.get_mut(index) {
+            if let Some(detector) = self.loops.as_mut() {
+                detector.written(index, *cell, value);
+            }
+            *cell = value;
+
+            if let Some(decoded) = self.decoded.get_mut(index) {
+                *decoded = None;
+            }
+        }
+    }
+
+    pub(crate) fn usage(&self) -> Usage {
+        Usage {
+            executed: self.executed,
+            outputs: self.outputs,
+            started: self.started,
+        }
+    }
+
+    pub(crate) fn set_usage(&mut self, usage: Usage) {
+        self.executed = usage.executed;
+        self.outputs = usage.outputs;
+        self.started = usage.started;
+    }
+
+    /// Shrink memory back to `len` cells after undoing writes that grew it.
+    pub(crate) fn truncate_memory(&mut self, len: usize) {
+        self.data.truncate(len);
+        self.decoded.truncate(len);
+    }
+
+    /// Values queued for input but not yet consumed.
+    pub fn pending_input(&self) -> &VecDeque<i64> {
+        &self.input
+    }
+
+    /// The complete memory of the program, including any self-modifications.
+    pub fn memory(&self) -> &[i64] {
+        &self.data
+    }
+
+    /// Decode the instruction at the current index, with the arity of the
+    /// handler registered for its opcode.
+    pub fn instruction(&self) -> Result<Instruction, IntcodeError> {
+        let word = self.value(self.index)?;
+
+        self.parse(word).map_err(|error| IntcodeError::decode(self.index, word, error))
+    }
+
+    fn parse(&self, word: i64) -> Result<Instruction, DecodeError> {
+        let code = Instruction::normalize(word)?[3];
+        let handler = self.handler(code).ok_or(DecodeError::UnknownOpcode(code))?;
+
+        Instruction::parse_with_arity(word, handler.arity())
+    }
+
+    fn handler(&self, code: u32) -> Option<&Handler> {
+        self.instructions.get(code)
+    }
+
+    /// Execute opcode `code` with `handler` from now on, replacing the
+    /// built-in instruction if there is one.
+    ///
+    /// Panics unless `code` fits in two digits and the handler takes at
+    /// most three parameters.
+    pub fn register_instruction(&mut self, code: u32, handler: impl InstructionHandler + 'static) {
+        Arc::make_mut(&mut self.instructions).register(code, Handler::Registered(Arc::new(handler)));
+        self.decoded.clear();
+    }
+
+    /// Turn the decode cache on or off. It is on by default; turning it off
+    /// decodes every instruction again each time it executes.
+    pub fn set_decode_cache(&mut self, enabled: bool) {
+        self.cache = enabled;
+        self.decoded.clear();
+    }
+
+    /// Restrict the resources the program may use from here on. Steps and
+    /// outputs count from this call, the timeout from the next instruction
+    /// executed.
+    pub fn set_limits(&mut self, limits: Limits) {
+        self.limits = limits;
+        self.executed = 0;
+        self.outputs = 0;
+        self.started = None;
+    }
+
+    pub fn limits(&self) -> Limits {
+        self.limits
+    }
+
+    /// The loop detection mode set with `detect_loops`, if any.
+    pub fn loop_detection(&self) -> Option<LoopDetection> {
+        self.loops.as_ref().map(Detector::mode)
+    }
+
+    /// Whether `register_instruction` replaced the built-in instruction set.
+    pub(crate) fn has_registered_instructions(&self) -> bool {
+        !Arc::ptr_eq(&self.instructions, &InstructionSet::builtin())
+    }
+
+    pub(crate) fn has_devices(&self) -> bool {
+        !self.devices.is_empty()
+    }
+
+    /// Fail with `IntcodeError::InfiniteLoop` once the program repeats a
+    /// state it was in before, see `loops`. Detection is off while devices
+    /// are mapped.
+    pub fn detect_loops(&mut self, mode: LoopDetection) {
+        self.loops = Some(Detector::new(mode, &self.data));
+    }
+
+    /// Fail with the step or time limit executing another instruction would
+    /// exceed.
+    fn check_limits(&mut self) -> Result<(), IntcodeError> {
+        if let Some(limit) = self.limits.steps {
+            if self.executed >= limit {
+                return Err(IntcodeError::StepLimitExceeded { limit });
+            }
+        }
+
+        if let Some(limit) = self.limits.timeout {
+            let started = *self.started.get_or_insert_with(Instant::now);
+            if self.executed.is_multiple_of(TIMEOUT_INTERVAL) && started.elapsed() > limit {
+                return Err(IntcodeError::Timeout { limit });
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Decode `word`, read from the current index, reusing earlier decodes
+    /// of the same address.
+    fn decode(&mut self, word: i64) -> Result<Instruction, IntcodeError> {
+        if let Some(Some(instruction)) = self.decoded.get(self.index) {
+            return Ok(*instruction);
+        }
+
+        let instruction = self.parse(word).map_err(|error| IntcodeError::decode(self.index, word, error))?;
+
+        if self.cache && self.index < self.data.len() && self.device_at(self.index).is_none() {
+            if self.decoded.len() < self.data.len() {
+                self.decoded.resize(self.data.len(), None);
+            }
+            self.decoded[self.index] = Some(instruction);
+        }
+
+        Ok(instruction)
+    }
+
+    fn address_of(&self, parameter: &Parameter) -> usize {
+        self.index + 1 + parameter.position
+    }
+
+    fn relative(&self, offset: i64) -> Result<usize, IntcodeError> {
+        let address = self.relative_base.checked_add(offset)
+            .ok_or(IntcodeError::ArithmeticOverflow { address: self.index })?;
+
+        to_address(address)
+    }
+
+    fn get_parameter(&self, parameter: &Parameter) -> Result<i64, IntcodeError> {
+        let address = self.address_of(parameter);
+
+        match parameter.mode {
+            ParameterMode::Position => self.value(to_address(self.value(address)?)?),
+            ParameterMode::Immediate => self.value(address),
+            ParameterMode::Relative => self.value(self.relative(self.value(address)?)?),
+        }
+    }
+
+    fn destination(&self, parameter: &Parameter) -> Result<usize, IntcodeError> {
+        let value = self.value(self.address_of(parameter))?;
+
+        match parameter.mode {
+            ParameterMode::Position => to_address(value),
+            ParameterMode::Relative => self.relative(value),
+            ParameterMode::Immediate => Err(IntcodeError::InvalidParameterMode {
+                address: self.index,
+                mode: 1,
+            }),
+        }
+    }
+
+    /// Resolve every parameter of `instruction` to the value it reads, or
+    /// for written parameters, to the destination address.
+    fn resolve(&self, instruction: &Instruction, write_parameters: &[usize]) -> Result<[i64; 3], IntcodeError> {
+        let mut values = [0; 3];
+
+        for parameter in instruction.parameters() {
+            values[parameter.position] = if write_parameters.contains(&parameter.position) {
+                self.destination(parameter)? as i64
+            } else {
+                self.get_parameter(parameter)?
+            };
+        }
+
+        Ok(values)
+    }
+
+    /// Undo what an instruction that failed did through its `Context`, so
+    /// the program is left as it was before. Writes that reached a device
+    /// cannot be undone.
+    fn roll_back(&mut self, execution: &Execution, relative_base: i64, memory_len: usize) {
+        for write in execution.writes().iter().rev() {
+            self.restore(write.address, write.old);
+        }
+        self.truncate_memory(memory_len);
+
+        if let Some(value) = execution.input {
+            self.unread_input(value);
+        }
+        self.relative_base = relative_base;
+    }
+
+    /// Execute the instruction at the current index and describe what it did.
+    ///
+    /// An input instruction without queued input is left unexecuted and
+    /// reported with `Status::NeedsInput`, so executing again after pushing
+    /// input resumes it.
+    pub fn execute(&mut self) -> Result<Execution, IntcodeError> {
+        let address = self.index;
+        // Read the word once, reading a device can change what it holds.
+        let word = self.value(address)?;
+        let instruction = self.decode(word)?;
+        let handler = self.handler(instruction.opcode.code())
+            .cloned()
+            .ok_or(IntcodeError::UnknownOpcode { address, opcode: word })?;
+        self.check_limits()?;
+        let parameters = self.resolve(&instruction, handler.write_parameters())?;
+        let arity = instruction.parameters().len();
+
+        let mut execution = Execution {
+            address,
+            word,
+            opcode: instruction.opcode,
+            parameters,
+            arity,
+            writes: [MemoryWrite::default(); MAX_WRITES],
+            written: 0,
+            input: None,
+            output: None,
+            status: None,
+        };
+        let mut next = address + instruction.size();
+        let mut jumped = false;
+        let relative_base = self.relative_base;
+        let memory_len = self.data.len();
+
+        let effect = match handler.execute(&mut Context::new(self, &mut execution), &parameters[..arity]) {
+            Ok(effect) => effect,
+            Err(error) => {
+                self.roll_back(&execution, relative_base, memory_len);
+                return Err(error);
+            },
+        };
+
+        match effect {
+            Effect::Next => {},
+            Effect::Output(value) => {
+                if let Some(limit) = self.limits.outputs {
+                    if self.outputs >= limit {
+                        self.roll_back(&execution, relative_base, memory_len);
+                        return Err(IntcodeError::OutputLimitExceeded { limit });
+                    }
+                }
+
+                self.outputs += 1;
+                execution.output = Some(value);
+                execution.status = Some(Status::Output(value));
+            },
+            Effect::Jump(target) => {
+                next = target;
+                jumped = true;
+            },
+            Effect::NeedsInput => {
+                execution.status = Some(Status::NeedsInput);
+                return Ok(execution);
+            },
+            Effect::Halt => {
+                execution.status = Some(Status::Halted);
+                self.retire();
+                return Ok(execution);
+            },
+        }
+
+        self.index = next;
+        self.retire();
+
+        if let Some(detector) = self.loops.as_mut() {
+            detector.executed(address, instruction.size());
+            if execution.input.is_some() {
+                detector.reset();
+            }
+
+            // Detection cannot see what devices will read next.
+            if jumped && self.devices.is_empty() {
+                if let Some(found) = detector.jumped(self.index, self.relative_base, &self.input, &self.data) {
+                    return Err(IntcodeError::InfiniteLoop { start: found.start, end: found.end, period: found.period });
+                }
+            }
+        }
+
+        Ok(execution)
+    }
+
+    /// Execute the instruction at the current index.
+    ///
+    /// Returns the status to report when the instruction interrupts
+    /// execution. An input instruction without queued input is left
+    /// unexecuted, so stepping again after pushing input resumes it.
+    pub fn step(&mut self) -> Result<Option<Status>, IntcodeError> {
+        Ok(self.execute()?.status)
+    }
+
+    /// Execute until the program needs input, produces output or halts.
+    ///
+    /// Memory, index and pending input are kept intact between calls, so
+    /// several programs can be driven cooperatively from a single thread.
+    pub fn run(&mut self) -> Result<Status, IntcodeError> {
+        loop {
+            if let Some(status) = self.step()? {
+                return Ok(status);
+            }
+        }
+    }
+
+    /// Execute the program until it reaches `Opcode::Exit`.
+    ///
+    /// `input` is read every time the program runs out of queued input and
+    /// `output` receives every value emitted by `Opcode::Output`.
+    pub fn run_with(
+        &mut self,
+        input: &mut dyn InputDevice,
+        output: &mut dyn OutputDevice,
+    ) -> Result<(), IntcodeError> {
+        loop {
+            match self.run()? {
+                Status::NeedsInput => {
+                    let value = input.read()?;
+                    self.push_input(value);
+                },
+                Status::Output(value) => output.write(value)?,
+                Status::Halted => return Ok(()),
+            }
+        }
+    }
+
+    /// Like `Program::run_with`, additionally handing every executed
+    /// instruction to `tracer`.
+    pub fn run_traced(
+        &mut self,
+        input: &mut dyn InputDevice,
+        output: &mut dyn OutputDevice,
+        tracer: &mut dyn Tracer,
+    ) -> Result<(), IntcodeError> {
+        loop {
+            let execution = self.execute()?;
+
+            if execution.status == Some(Status::NeedsInput) {
+                let value = input.read()?;
+                self.push_input(value);
+                continue;
+            }
+
+            tracer.record(&execution)?;
+
+            match execution.status {
+                Some(Status::Output(value)) => output.write(value)?,
+                Some(Status::Halted) => return Ok(()),
+                _ => {},
+            }
+        }
+    }
+}
+
+impl From<Vec<i64>> for Program {
+    fn from(data: Vec<i64>) -> Program {
+        Program {
+            data,
+            index: 0,
+            relative_base: 0,
+            input: VecDeque::new(),
+            decoded: vec![],
+            cache: true,
+            instructions: InstructionSet::builtin(),
+            devices: vec![],
+            loops: None,
+            limits: Limits::default(),
+            executed: 0,
+            outputs: 0,
+            started: None,
+        }
+    }
+}
+
+impl std::ops::Index<usize> for Program {
+    type Output = i64;
+
+    fn index(&self, index: usize) -> &Self::Output {
+        self.data.get(index).unwrap_or(&0)
+    }
+}
+
+/// Parse a comma separated line of intcode into memory cells.
+///
+/// Columns in parse errors are one-based character offsets into `raw`.
+pub fn parse_program_into_instructions(raw: &str) -> Result<Vec<i64>, IntcodeError> {
+    let mut column = 1;
+
+    raw.split(',')
+        .map(|token| {
+            let start = column + (token.len() - token.trim_start().len());
+            column += token.chars().count() + 1;
+
+            token.trim().parse::<i64>().map_err(|_| IntcodeError::Parse {
+                column: start,
+                token: token.trim().to_string(),
+            })
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn run_with_input(source: &str, input: i64) -> Vec<i64> {
+        let mut program = Program::from(parse_program_into_instructions(source).unwrap());
+        let mut outputs = vec![];
+        program.run_with(&mut VecDeque::from(vec![input]), &mut outputs).unwrap();
+
+        outputs
+    }
+
+    fn run(program: &mut Program) -> Result<(), IntcodeError> {
+        program.run_with(&mut VecDeque::new(), &mut vec![])
+    }
+
+    #[test]
+    fn it_decodes_raw_program_instructions() {
+        let raw_line: &str = "1002,4,3,4,33";
+        let instructions = parse_program_into_instructions(raw_line);
+
+        assert_eq!(instructions, Ok(vec![1002, 4, 3, 4, 33]));
+    }
+
+    #[test]
+    fn it_runs_addition_and_multiplication() {
+        let mut program = Program::from(parse_program_into_instructions("1,9,10,3,2,3,11,0,99,30,40,50").unwrap());
+        run(&mut program).unwrap();
+
+        assert_eq!(program[0], 3500);
+        assert_eq!(program.memory(), &[3500, 9, 10, 70, 2, 3, 11, 0, 99, 30, 40, 50]);
+    }
+
+    #[test]
+    fn it_honours_immediate_mode() {
+        let mut program = Program::from(parse_program_into_instructions("1002,4,3,4,33").unwrap());
+        run(&mut program).unwrap();
+
+        assert_eq!(program[4], 99);
+    }
+
+    #[test]
+    fn it_compares_input_using_jumps() {
+        let source = "3,9,8,9,10,9,4,9,99,-1,8";
+
+        assert_eq!(run_with_input(source, 8), vec![1]);
+        assert_eq!(run_with_input(source, 7), vec![0]);
+
+        let source = "3,3,1105,-1,9,1101,0,0,12,4,12,99,1";
+
+        assert_eq!(run_with_input(source, 0), vec![0]);
+        assert_eq!(run_with_input(source, 42), vec![1]);
+    }
+
+    #[test]
+    fn it_reports_parse_errors_with_column() {
+        assert_eq!(parse_program_into_instructions("1,2, x3,99"), Err(IntcodeError::Parse {
+            column: 6,
+            token: "x3".to_string(),
+        }));
+    }
+
+    #[test]
+    fn it_reports_execution_errors_instead_of_panicking() {
+        let mut program = Program::from(vec![1, 0, 0, 0, 42]);
+        assert_eq!(run(&mut program), Err(IntcodeError::UnknownOpcode { address: 4, opcode: 42 }));
+        assert_eq!(program.index, 4);
+
+        let mut program = Program::from(vec![11101, 0, 0, 0, 99]);
+        assert_eq!(run(&mut program), Err(IntcodeError::InvalidParameterMode { address: 0, mode: 1 }));
+
+        let mut program = Program::from(vec![1002, 5, 2, 5, 99, i64::MAX]);
+        assert_eq!(run(&mut program), Err(IntcodeError::ArithmeticOverflow { address: 0 }));
+
+        let mut program = Program::from(vec![1, -1, 0, 0, 99]);
+        assert_eq!(run(&mut program), Err(IntcodeError::NegativeAddress { value: -1 }));
+
+        let mut program = Program::from(vec![301, 0, 0, 0, 99]);
+        assert_eq!(run(&mut program), Err(IntcodeError::InvalidParameterMode { address: 0, mode: 3 }));
+    }
+
+    #[test]
+    fn it_fails_when_input_runs_out() {
+        let mut program = Program::from(vec![3, 0, 3, 0, 99]);
+
+        assert_eq!(
+            program.run_with(&mut VecDeque::from(vec![1]), &mut vec![]),
+            Err(IntcodeError::InputUnavailable),
+        );
+        assert_eq!(program.index, 2);
+    }
+
+    #[test]
+    fn it_pauses_for_input_and_resumes() {
+        let mut program = Program::from(parse_program_into_instructions("3,9,8,9,10,9,4,9,99,-1,8").unwrap());
+
+        assert_eq!(program.run(), Ok(Status::NeedsInput));
+        assert_eq!(program.index, 0);
+        assert_eq!(program.run(), Ok(Status::NeedsInput));
+
+        program.push_input(8);
+        assert_eq!(program.run(), Ok(Status::Output(1)));
+        assert_eq!(program.run(), Ok(Status::Halted));
+        assert_eq!(program.run(), Ok(Status::Halted));
+        assert!(program.pending_input().is_empty());
+    }
+
+    #[test]
+    fn it_grows_memory_beyond_the_loaded_image() {
+        let mut program = Program::from(vec![1101, 2, 3, 10, 4, 10, 4, 1000, 99]);
+        let mut outputs = vec![];
+        program.run_with(&mut VecDeque::new(), &mut outputs).unwrap();
+
+        assert_eq!(outputs, vec![5, 0]);
+        assert_eq!(program.memory().len(), 11);
+        assert_eq!(program[1000], 0);
+    }
+
+    #[test]
+    fn it_supports_relative_mode_and_large_numbers() {
+        let quine = "109,1,204,-1,1001,100,1,100,1008,100,16,101,1006,101,0,99";
+        let mut program = Program::from(parse_program_into_instructions(quine).unwrap());
+        let mut outputs = vec![];
+        program.run_with(&mut VecDeque::new(), &mut outputs).unwrap();
+
+        assert_eq!(outputs, parse_program_into_instructions(quine).unwrap());
+
+        assert_eq!(run_with_input("1102,34915192,34915192,7,4,7,99,0", 0), vec![1219070632396864]);
+        assert_eq!(run_with_input("104,1125899906842624,99", 0), vec![1125899906842624]);
+        assert_eq!(run_with_input("109,10,203,0,204,0,99", 42), vec![42]);
+    }
+
+    #[test]
+    fn it_decodes_instructions_again_after_they_are_overwritten() {
+        // Prints #17, rewrites its first instruction into `out [17]`, clears
+        // the flag at 17 and jumps back to print it.
+        let source = vec![104, 17, 1006, 17, 16, 1101, 0, 4, 0, 1101, 0, 0, 17, 1105, 1, 0, 99, 1];
+
+        for cache in [true, false].iter() {
+            let mut program = Program::from(source.clone());
+            program.set_decode_cache(*cache);
+            let mut outputs = vec![];
+            program.run_with(&mut VecDeque::new(), &mut outputs).unwrap();
+
+            assert_eq!(outputs, vec![17, 0]);
+        }
+    }
+
+    #[test]
+    fn it_stops_at_the_step_limit() {
+        let mut program = Program::from(vec![1101, 1, 1, 5, 99, 0]);
+        program.set_limits(Limits::new().steps(2));
+        assert_eq!(program.run(), Ok(Status::Halted));
+
+        let mut program = Program::from(vec![1101, 1, 1, 5, 99, 0]);
+        program.set_limits(Limits::new().steps(1));
+        assert_eq!(program.run(), Err(IntcodeError::StepLimitExceeded { limit: 1 }));
+        assert_eq!(program.index, 4);
+    }
+
+    #[test]
+    fn it_refuses_to_grow_memory_beyond_the_limit() {
+        let mut program = Program::from(vec![1101, 1, 1, 100, 99]);
+        program.set_limits(Limits::new().memory(50));
+
+        assert_eq!(program.run(), Err(IntcodeError::MemoryLimitExceeded { address: 100, limit: 50 }));
+        assert_eq!(program.index, 0);
+        assert_eq!(program.memory().len(), 5);
+        assert_eq!(program.write(49, 7), Ok(()));
+    }
+
+    #[test]
+    fn it_stops_at_the_output_limit() {
+        let mut program = Program::from(vec![104, 1, 1105, 1, 0]);
+        program.set_limits(Limits::new().outputs(3));
+        let mut outputs = vec![];
+
+        assert_eq!(
+            program.run_with(&mut VecDeque::new(), &mut outputs),
+            Err(IntcodeError::OutputLimitExceeded { limit: 3 }),
+        );
+        assert_eq!(outputs, vec![1, 1, 1]);
+    }
+
+    #[test]
+    fn it_times_out() {
+        let mut program = Program::from(vec![1105, 1, 0]);
+        let limit = std::time::Duration::from_millis(1);
+        program.set_limits(Limits::new().timeout(limit));
+
+        assert_eq!(program.run(), Err(IntcodeError::Timeout { limit }));
+    }
+}
@@ -0,0 +1,241 @@
+//! Brute force search over patched copies of a program.
+//!
+//! Every combination of values for the patched addresses is written into a
+//! fresh copy of the program, which then runs to completion. Combinations
+//! whose final memory satisfies the predicate are reported. Candidates are
+//! split evenly across threads, and each one runs under a step limit so a
+//! patch that never halts cannot hang its thread.
+
+use std::collections::VecDeque;
+use std::convert::TryFrom;
+use std::fmt;
+use std::ops::RangeInclusive;
+use std::thread;
+
+use crate::error::IntcodeError;
+use crate::limits::Limits;
+use crate::program::Program;
+
+/// Steps each candidate may execute unless `Search::max_steps` says otherwise.
+const DEFAULT_MAX_STEPS: u64 = 1_000_000;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum SearchError {
+    /// The number of combinations does not fit in a `usize`.
+    TooManyCandidates,
+}
+
+impl fmt::Display for SearchError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::TooManyCandidates => write!(f, "too many combinations to search"),
+        }
+    }
+}
+
+impl std::error::Error for SearchError {}
+
+/// Values written to the patched addresses, in the order they were added.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Match {
+    pub values: Vec<i64>,
+}
+
+#[derive(Debug, Clone)]
+pub struct Search<'a> {
+    program: &'a Program,
+    patches: Vec<(usize, RangeInclusive<i64>)>,
+    threads: usize,
+    max_steps: u64,
+}
+
+/// Number of values in the range, `None` when that does not fit in a `usize`.
+fn len(values: &RangeInclusive<i64>) -> Option<usize> {
+    if values.is_empty() {
+        return Some(0);
+    }
+
+    let span = values.end().checked_sub(*values.start())?;
+    usize::try_from(span).ok()?.checked_add(1)
+}
+
+/// Number of combinations of patches with `lens` values each, `None` on
+/// overflow.
+fn combinations(lens: &[usize]) -> Option<usize> {
+    lens.iter().try_fold(1usize, |product, len| product.checked_mul(*len))
+}
+
+impl<'a> Search<'a> {
+    /// Search over `program`, using every available core by default.
+    pub fn new(program: &'a Program) -> Search<'a> {
+        Search {
+            program,
+            patches: vec![],
+            threads: thread::available_parallelism().map(|threads| threads.get()).unwrap_or(1),
+            max_steps: DEFAULT_MAX_STEPS,
+        }
+    }
+
+    /// Try every value in `values` at `address`.
+    pub fn patch(mut self, address: usize, values: RangeInclusive<i64>) -> Search<'a> {
+        self.patches.push((address, values));
+        self
+    }
+
+    pub fn threads(mut self, threads: usize) -> Search<'a> {
+        self.threads = threads.max(1);
+        self
+    }
+
+    /// Give up on a candidate after `steps` instructions, treating it as a
+    /// failure.
+    pub fn max_steps(mut self, steps: u64) -> Search<'a> {
+        self.max_steps = steps;
+        self
+    }
+
+    /// Number of combinations the search will try, `None` when there are
+    /// more than a `usize` can count.
+    pub fn candidates(&self) -> Option<usize> {
+        combinations(&self.lens()?)
+    }
+
+    /// Number of values each patch tries.
+    fn lens(&self) -> Option<Vec<usize>> {
+        self.patches.iter().map(|(_, values)| len(values)).collect()
+    }
+
+    /// The `number`th combination, with the last patch varying fastest,
+    /// given the `lens` of every patch.
+    fn candidate(&self, mut number: usize, lens: &[usize]) -> Vec<i64> {
+        let mut values = vec![0; self.patches.len()];
+
+        for (position, ((_, range), len)) in self.patches.iter().zip(lens).enumerate().rev() {
+            values[position] = range.start() + (number % len) as i64;
+            number /= len;
+        }
+
+        values
+    }
+
+    fn evaluate(&self, values: &[i64]) -> Result<Program, IntcodeError> {
+        let mut program = self.program.clone();
+        for ((address, _), value) in self.patches.iter().zip(values) {
+            program.write(*address, *value)?;
+        }
+
+        let limits = program.limits();
+        let steps = limits.steps.map_or(self.max_steps, |steps| steps.min(self.max_steps));
+        program.set_limits(Limits { steps: Some(steps), ..limits });
+
+        program.run_with(&mut VecDeque::new(), &mut vec![])?;
+
+        Ok(program)
+    }
+
+    /// Every combination whose final memory satisfies `predicate`, in the
+    /// order the combinations are enumerated. Combinations whose program
+    /// fails, asks for input or runs out of steps never match.
+    pub fn run<P>(&self, predicate: P) -> Result<Vec<Match>, SearchError>
+    where
+        P: Fn(&[i64]) -> bool + Sync,
+    {
+        let lens = self.lens().ok_or(SearchError::TooManyCandidates)?;
+        let candidates = combinations(&lens).ok_or(SearchError::TooManyCandidates)?;
+        let threads = self.threads.min(candidates).max(1);
+        let chunk = candidates.div_ceil(threads);
+        let predicate = &predicate;
+        let lens = &lens;
+
+        Ok(thread::scope(|scope| {
+            let workers: Vec<_> = (0..threads)
+                .map(|worker| {
+                    let range = (worker * chunk)..candidates.min((worker + 1) * chunk);
+
+                    scope.spawn(move || {
+                        range
+                            .map(|number| self.candidate(number, lens))
+                            .filter(|values| match self.evaluate(values) {
+                                Ok(program) => predicate(program.memory()),
+                                Err(_) => false,
+                            })
+                            .map(|values| Match { values })
+                            .collect::<Vec<Match>>()
+                    })
+                })
+                .collect();
+
+            workers.into_iter()
+                .flat_map(|worker| worker.join().expect("search worker panicked"))
+                .collect()
+        }))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::program::parse_program_into_instructions;
+
+    fn program() -> Program {
+        Program::from(parse_program_into_instructions("1,9,10,3,2,3,11,0,99,30,40,50").unwrap())
+    }
+
+    #[test]
+    fn it_enumerates_every_combination() {
+        let program = program();
+        let search = Search::new(&program).patch(1, 0..=2).patch(2, 5..=6).patch(3, RangeInclusive::new(1, 0));
+        assert_eq!(search.candidates(), Some(0));
+
+        let search = Search::new(&program).patch(1, 0..=2).patch(2, 5..=6);
+        assert_eq!(search.candidates(), Some(6));
+        assert_eq!((0..6).map(|number| search.candidate(number, &[3, 2])).collect::<Vec<_>>(), vec![
+            vec![0, 5], vec![0, 6], vec![1, 5], vec![1, 6], vec![2, 5], vec![2, 6],
+        ]);
+    }
+
+    #[test]
+    fn it_finds_every_match_regardless_of_thread_count() {
+        let program = program();
+        let search = |threads| Search::new(&program)
+            .patch(1, 0..=11)
+            .patch(2, 0..=11)
+            .threads(threads)
+            .run(|memory| memory[0] == 3500)
+            .unwrap();
+
+        let matches = search(1);
+        assert!(matches.contains(&Match { values: vec![9, 10] }));
+        assert!(matches.contains(&Match { values: vec![10, 9] }));
+        assert_eq!(search(4), matches);
+        assert_eq!(search(200), matches);
+    }
+
+    #[test]
+    fn it_skips_failing_candidates() {
+        let program = Program::from(vec![1, 0, 0, 0, 99]);
+        let matches = Search::new(&program).patch(0, 0..=3).run(|_| true);
+
+        assert_eq!(matches, Ok(vec![Match { values: vec![1] }, Match { values: vec![2] }]));
+    }
+
+    #[test]
+    fn it_gives_up_on_candidates_that_never_halt() {
+        // Spins forever when the patched jump target is 0.
+        let program = Program::from(vec![1105, 1, 3, 99]);
+        let matches = Search::new(&program).patch(2, 0..=3).max_steps(100).run(|_| true);
+
+        assert_eq!(matches, Ok(vec![Match { values: vec![3] }]));
+    }
+
+    #[test]
+    fn it_rejects_searches_too_large_to_count() {
+        let program = program();
+        let search = Search::new(&program).patch(1, i64::MIN..=i64::MAX);
+        assert_eq!(search.candidates(), None);
+        assert_eq!(search.run(|_| true), Err(SearchError::TooManyCandidates));
+
+        let search = Search::new(&program).patch(1, 0..=i64::MAX).patch(2, 0..=i64::MAX);
+        assert_eq!(search.candidates(), None);
+    }
+}
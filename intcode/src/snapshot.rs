@@ -0,0 +1,366 @@
+//! Complete machine state saved as text, to checkpoint long runs or to hand
+//! a failing state to someone else.
+//!
+//! ```text
+//! intcode-snapshot 2
+//! index 8
+//! relative-base 0
+//! input
+//! output 1
+//! memory 3,9,8,9,10,9,4,9,99,1,8
+//! max-steps 100000
+//! loops exact
+//! ```
+//!
+//! The first line names the format and its version. Every other line holds
+//! a field name, a single space and the value; lists are comma separated
+//! and may be empty. The first five fields are required, each exactly once,
+//! in any order. `input` holds values queued but not yet consumed, `output`
+//! the values the program emitted before the snapshot was taken. Blank
+//! lines are ignored.
+//!
+//! The optional `max-steps`, `max-memory`, `max-outputs` and `timeout`
+//! fields carry the program's limits, the timeout in seconds with up to
+//! nine decimals. `loops` holds the loop detection mode, `exact` or
+//! `heuristic`. Version 1 snapshots, which have none of these, still load.
+//!
+//! Registered instructions and mapped devices cannot be written down, so
+//! programs using them are refused rather than saved without them. Nor
+//! does a snapshot hold how much of its limits a program has used, what
+//! loop detection has seen so far or the decode cache: a restored program
+//! counts its limits and looks for loops afresh.
+
+use std::fmt;
+use std::time::Duration;
+
+use crate::limits::Limits;
+use crate::loops::LoopDetection;
+use crate::program::{parse_program_into_instructions, Program};
+
+const HEADER: &str = "intcode-snapshot";
+const VERSION: &str = "2";
+/// Earlier versions this one can still read.
+const READABLE: &[&str] = &["1"];
+
+#[derive(Debug, PartialEq, Clone)]
+pub enum SnapshotError {
+    RegisteredInstructions,
+    MappedDevices,
+    MissingHeader,
+    UnsupportedVersion { version: String },
+    UnknownField { line: usize, field: String },
+    DuplicateField { line: usize, field: String },
+    MissingField { field: &'static str },
+    InvalidValue { line: usize, field: String, value: String },
+}
+
+impl fmt::Display for SnapshotError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::RegisteredInstructions => write!(f, "cannot snapshot a program with registered instructions"),
+            Self::MappedDevices => write!(f, "cannot snapshot a program with mapped devices"),
+            Self::MissingHeader => write!(f, "not an intcode snapshot"),
+            Self::UnsupportedVersion { version } => write!(f, "unsupported snapshot version {:?}", version),
+            Self::UnknownField { line, field } => write!(f, "line {}: unknown field {:?}", line, field),
+            Self::DuplicateField { line, field } => write!(f, "line {}: field {:?} appears twice", line, field),
+            Self::MissingField { field } => write!(f, "missing field {:?}", field),
+            Self::InvalidValue { line, field, value } => {
+                write!(f, "line {}: invalid {} {:?}", line, field, value)
+            },
+        }
+    }
+}
+
+impl std::error::Error for SnapshotError {}
+
+#[derive(Debug, PartialEq, Clone)]
+pub struct Snapshot {
+    pub memory: Vec<i64>,
+    pub index: usize,
+    pub relative_base: i64,
+    pub input: Vec<i64>,
+    pub output: Vec<i64>,
+    pub limits: Limits,
+    pub loops: Option<LoopDetection>,
+}
+
+fn write_list(f: &mut fmt::Formatter, field: &str, values: &[i64]) -> fmt::Result {
+    write!(f, "{}", field)?;
+    for (position, value) in values.iter().enumerate() {
+        write!(f, "{}{}", if position == 0 { ' ' } else { ',' }, value)?;
+    }
+
+    writeln!(f)
+}
+
+/// Seconds with up to nine decimals, as written by `Display`.
+fn parse_duration(value: &str) -> Option<Duration> {
+    let (seconds, fraction) = value.split_once('.').unwrap_or((value, "0"));
+    if fraction.is_empty() || fraction.len() > 9 || !fraction.bytes().all(|byte| byte.is_ascii_digit()) {
+        return None;
+    }
+
+    let nanos = format!("{:0<9}", fraction).parse().ok()?;
+    Some(Duration::new(seconds.parse().ok()?, nanos))
+}
+
+impl Snapshot {
+    /// Capture `program` together with the output it has produced so far.
+    ///
+    /// Fails for programs with registered instructions or mapped devices,
+    /// which a snapshot cannot hold.
+    pub fn capture(program: &Program, output: &[i64]) -> Result<Snapshot, SnapshotError> {
+        if program.has_registered_instructions() {
+            return Err(SnapshotError::RegisteredInstructions);
+        }
+        if program.has_devices() {
+            return Err(SnapshotError::MappedDevices);
+        }
+
+        Ok(Snapshot {
+            memory: program.memory().to_vec(),
+            index: program.index,
+            relative_base: program.relative_base,
+            input: program.pending_input().iter().copied().collect(),
+            output: output.to_vec(),
+            limits: program.limits(),
+            loops: program.loop_detection(),
+        })
+    }
+
+    /// A program that continues exactly where the captured one stopped.
+    pub fn restore(&self) -> Program {
+        let mut program = Program::from(self.memory.clone());
+        program.index = self.index;
+        program.relative_base = self.relative_base;
+        for value in self.input.iter() {
+            program.push_input(*value);
+        }
+        program.set_limits(self.limits);
+        if let Some(mode) = self.loops {
+            program.detect_loops(mode);
+        }
+
+        program
+    }
+
+    /// Read a snapshot in the format written by `Display`.
+    pub fn parse(text: &str) -> Result<Snapshot, SnapshotError> {
+        let mut lines = text.lines()
+            .enumerate()
+            .map(|(number, line)| (number + 1, line.trim()))
+            .filter(|(_, line)| !line.is_empty());
+
+        match lines.next().map(|(_, line)| line.split_once(' ')) {
+            Some(Some((HEADER, version))) if version == VERSION || READABLE.contains(&version) => {},
+            Some(Some((HEADER, version))) => {
+                return Err(SnapshotError::UnsupportedVersion { version: version.to_string() })
+            },
+            _ => return Err(SnapshotError::MissingHeader),
+        }
+
+        let mut memory = None;
+        let mut index = None;
+        let mut relative_base = None;
+        let mut input = None;
+        let mut output = None;
+        let mut limits = Limits::new();
+        let mut loops = None;
+
+        for (line, text) in lines {
+            let (field, value) = text.split_once(' ').unwrap_or((text, ""));
+            let value = value.trim();
+            let invalid = || SnapshotError::InvalidValue {
+                line,
+                field: field.to_string(),
+                value: value.to_string(),
+            };
+            let list = || match value {
+                "" => Ok(vec![]),
+                _ => parse_program_into_instructions(value).map_err(|_| invalid()),
+            };
+
+            let duplicate = match field {
+                "memory" => memory.replace(list()?).is_some(),
+                "index" => index.replace(value.parse::<usize>().map_err(|_| invalid())?).is_some(),
+                "relative-base" => relative_base.replace(value.parse::<i64>().map_err(|_| invalid())?).is_some(),
+                "input" => input.replace(list()?).is_some(),
+                "output" => output.replace(list()?).is_some(),
+                "max-steps" => limits.steps.replace(value.parse::<u64>().map_err(|_| invalid())?).is_some(),
+                "max-memory" => limits.memory.replace(value.parse::<usize>().map_err(|_| invalid())?).is_some(),
+                "max-outputs" => limits.outputs.replace(value.parse::<u64>().map_err(|_| invalid())?).is_some(),
+                "timeout" => limits.timeout.replace(parse_duration(value).ok_or_else(invalid)?).is_some(),
+                "loops" => loops.replace(match value {
+                    "exact" => LoopDetection::Exact,
+                    "heuristic" => LoopDetection::Heuristic,
+                    _ => return Err(invalid()),
+                }).is_some(),
+                _ => return Err(SnapshotError::UnknownField { line, field: field.to_string() }),
+            };
+
+            if duplicate {
+                return Err(SnapshotError::DuplicateField { line, field: field.to_string() });
+            }
+        }
+
+        Ok(Snapshot {
+            memory: memory.ok_or(SnapshotError::MissingField { field: "memory" })?,
+            index: index.ok_or(SnapshotError::MissingField { field: "index" })?,
+            relative_base: relative_base.ok_or(SnapshotError::MissingField { field: "relative-base" })?,
+            input: input.ok_or(SnapshotError::MissingField { field: "input" })?,
+            output: output.ok_or(SnapshotError::MissingField { field: "output" })?,
+            limits,
+            loops,
+        })
+    }
+}
+
+impl fmt::Display for Snapshot {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(f, "{} {}", HEADER, VERSION)?;
+        writeln!(f, "index {}", self.index)?;
+        writeln!(f, "relative-base {}", self.relative_base)?;
+        write_list(f, "input", &self.input)?;
+        write_list(f, "output", &self.output)?;
+        write_list(f, "memory", &self.memory)?;
+
+        if let Some(steps) = self.limits.steps {
+            writeln!(f, "max-steps {}", steps)?;
+        }
+        if let Some(cells) = self.limits.memory {
+            writeln!(f, "max-memory {}", cells)?;
+        }
+        if let Some(outputs) = self.limits.outputs {
+            writeln!(f, "max-outputs {}", outputs)?;
+        }
+        if let Some(timeout) = self.limits.timeout {
+            writeln!(f, "timeout {}.{:09}", timeout.as_secs(), timeout.subsec_nanos())?;
+        }
+        match self.loops {
+            Some(LoopDetection::Exact) => writeln!(f, "loops exact"),
+            Some(LoopDetection::Heuristic) => writeln!(f, "loops heuristic"),
+            None => Ok(()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::device::CycleCounter;
+    use crate::handler::{Context, Effect, InstructionHandler};
+    use crate::program::Status;
+    use crate::IntcodeError;
+    use std::sync::Arc;
+
+    const SNAPSHOT: &str = "\
+intcode-snapshot 2
+index 2
+relative-base 0
+input 8
+output
+memory 3,9,8,9,10,9,4,9,99,8,8
+";
+
+    #[test]
+    fn it_writes_the_documented_format() {
+        let mut program = Program::from(vec![3, 9, 8, 9, 10, 9, 4, 9, 99, -1, 8]);
+        program.push_input(8);
+        program.push_input(8);
+        program.step().unwrap();
+
+        assert_eq!(Snapshot::capture(&program, &[]).unwrap().to_string(), SNAPSHOT);
+        assert_eq!(Snapshot::parse(SNAPSHOT).map(|snapshot| snapshot.restore()), Ok(program));
+    }
+
+    #[test]
+    fn it_resumes_from_a_restored_snapshot() {
+        let mut program = Program::from(vec![3, 9, 8, 9, 10, 9, 4, 9, 99, -1, 8]);
+        program.push_input(8);
+        program.step().unwrap();
+
+        let text = Snapshot::capture(&program, &[]).unwrap().to_string();
+        let mut restored = Snapshot::parse(&text).unwrap().restore();
+
+        assert_eq!(restored.run(), Ok(Status::Output(1)));
+        assert_eq!(program.run(), Ok(Status::Output(1)));
+        assert_eq!(restored, program);
+    }
+
+    #[test]
+    fn it_rejects_malformed_snapshots() {
+        assert_eq!(Snapshot::parse("memory 99"), Err(SnapshotError::MissingHeader));
+        assert_eq!(Snapshot::parse("intcode-snapshot 3\n"), Err(SnapshotError::UnsupportedVersion {
+            version: "3".to_string(),
+        }));
+        assert_eq!(Snapshot::parse(&SNAPSHOT.replace("index 2", "index -2")), Err(SnapshotError::InvalidValue {
+            line: 2,
+            field: "index".to_string(),
+            value: "-2".to_string(),
+        }));
+        assert_eq!(Snapshot::parse(&SNAPSHOT.replace("input 8", "input 8\ninput 9")), Err(
+            SnapshotError::DuplicateField { line: 5, field: "input".to_string() },
+        ));
+        assert_eq!(Snapshot::parse(&SNAPSHOT.replace("output\n", "")), Err(SnapshotError::MissingField {
+            field: "output",
+        }));
+        assert_eq!(Snapshot::parse(&SNAPSHOT.replace("output", "outputs")), Err(SnapshotError::UnknownField {
+            line: 5,
+            field: "outputs".to_string(),
+        }));
+    }
+
+    #[test]
+    fn it_carries_limits_and_loop_detection() {
+        let mut program = Program::from(vec![1105, 1, 0]);
+        program.set_limits(Limits::new().steps(1000).memory(64).outputs(10).timeout(Duration::from_millis(1500)));
+        program.detect_loops(LoopDetection::Heuristic);
+
+        let text = Snapshot::capture(&program, &[]).unwrap().to_string();
+        assert!(text.ends_with("max-steps 1000\nmax-memory 64\nmax-outputs 10\ntimeout 1.500000000\nloops heuristic\n"));
+
+        let mut restored = Snapshot::parse(&text).unwrap().restore();
+        assert_eq!(restored.limits(), program.limits());
+        assert_eq!(restored.loop_detection(), Some(LoopDetection::Heuristic));
+        assert!(matches!(restored.run(), Err(IntcodeError::InfiniteLoop { .. })));
+    }
+
+    #[test]
+    fn it_reads_earlier_versions() {
+        let snapshot = Snapshot::parse(&SNAPSHOT.replace("snapshot 2", "snapshot 1")).unwrap();
+
+        assert_eq!(snapshot.limits, Limits::new());
+        assert_eq!(snapshot.loops, None);
+        assert_eq!(Snapshot::parse(&format!("{}timeout 2.5\n", SNAPSHOT)).unwrap().limits.timeout, Some(
+            Duration::from_millis(2500),
+        ));
+        assert_eq!(Snapshot::parse(&format!("{}loops always\n", SNAPSHOT)), Err(SnapshotError::InvalidValue {
+            line: 7,
+            field: "loops".to_string(),
+            value: "always".to_string(),
+        }));
+    }
+
+    struct Nop;
+
+    impl InstructionHandler for Nop {
+        fn arity(&self) -> usize {
+            0
+        }
+
+        fn execute(&self, _: &mut Context, _: &[i64]) -> Result<Effect, IntcodeError> {
+            Ok(Effect::Next)
+        }
+    }
+
+    #[test]
+    fn it_refuses_state_it_cannot_hold() {
+        let mut program = Program::from(vec![42, 99]);
+        program.register_instruction(42, Nop);
+        assert_eq!(Snapshot::capture(&program, &[]), Err(SnapshotError::RegisteredInstructions));
+
+        let mut program = Program::from(vec![99]);
+        program.map_device(10, Arc::new(CycleCounter::new())).unwrap();
+        assert_eq!(Snapshot::capture(&program, &[]), Err(SnapshotError::MappedDevices));
+    }
+}
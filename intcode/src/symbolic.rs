@@ -0,0 +1,488 @@
+//! Symbolic execution of programs built from additions and multiplications.
+//!
+//! Chosen memory cells hold variables instead of numbers, so every cell
+//! ends up as a polynomial in those variables. The program itself, meaning
+//! its opcodes and the addresses it writes to, has to stay concrete. A read
+//! through an address that depends on a variable makes the result unknown,
+//! which is only an error once the unknown cell is needed.
+
+use std::cmp::Reverse;
+use std::collections::BTreeMap;
+use std::fmt;
+use std::ops::RangeInclusive;
+
+use crate::error::IntcodeError;
+use crate::instruction::{Instruction, Opcode, ParameterMode};
+
+#[derive(Debug, PartialEq, Clone)]
+pub enum SymbolicError {
+    /// Only addition, multiplication and exit can be executed symbolically.
+    Unsupported { address: usize, opcode: Opcode },
+    /// An opcode or write destination at `address` depends on a variable.
+    SymbolicControl { address: usize },
+    /// The cell at `address` was computed from a read through a symbolic address.
+    Unknown { address: usize },
+    /// Solving needs a variable the polynomial is at most linear in.
+    Nonlinear,
+    /// The polynomial uses a variable without a name or range.
+    MissingVariable { variable: usize },
+    Intcode(IntcodeError),
+}
+
+impl fmt::Display for SymbolicError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::Unsupported { address, opcode } => {
+                write!(f, "cannot execute {} at address {} symbolically", opcode.mnemonic(), address)
+            },
+            Self::SymbolicControl { address } => write!(f, "instruction at address {} depends on a variable", address),
+            Self::Unknown { address } => write!(f, "address {} depends on a read through a variable address", address),
+            Self::Nonlinear => write!(f, "polynomial is not linear in any variable"),
+            Self::MissingVariable { variable } => write!(f, "variable {} has no name or range", variable),
+            Self::Intcode(error) => error.fmt(f),
+        }
+    }
+}
+
+impl std::error::Error for SymbolicError {}
+
+impl From<IntcodeError> for SymbolicError {
+    fn from(error: IntcodeError) -> SymbolicError {
+        SymbolicError::Intcode(error)
+    }
+}
+
+/// A polynomial with integer coefficients. Each term is keyed by the sorted
+/// indices of the variables multiplied together, so `x0 * x0 * x1` is
+/// `[0, 0, 1]` and the constant term is `[]`.
+#[derive(Debug, PartialEq, Clone, Default)]
+pub struct Polynomial {
+    terms: BTreeMap<Vec<usize>, i64>,
+}
+
+impl Polynomial {
+    pub fn constant(value: i64) -> Polynomial {
+        let mut polynomial = Polynomial::default();
+        if value != 0 {
+            polynomial.terms.insert(vec![], value);
+        }
+
+        polynomial
+    }
+
+    pub fn variable(variable: usize) -> Polynomial {
+        let mut polynomial = Polynomial::default();
+        polynomial.terms.insert(vec![variable], 1);
+
+        polynomial
+    }
+
+    /// The value of the polynomial, if it does not depend on any variable.
+    pub fn as_constant(&self) -> Option<i64> {
+        match self.terms.iter().next() {
+            None => Some(0),
+            Some((monomial, coefficient)) if monomial.is_empty() && self.terms.len() == 1 => Some(*coefficient),
+            _ => None,
+        }
+    }
+
+    fn add_term(&mut self, monomial: Vec<usize>, coefficient: i64) -> Option<()> {
+        let sum = self.terms.get(&monomial).copied().unwrap_or(0).checked_add(coefficient)?;
+
+        if sum == 0 {
+            self.terms.remove(&monomial);
+        } else {
+            self.terms.insert(monomial, sum);
+        }
+
+        Some(())
+    }
+
+    /// Sum of both polynomials, or `None` if a coefficient overflows.
+    pub fn checked_add(&self, other: &Polynomial) -> Option<Polynomial> {
+        let mut sum = self.clone();
+        for (monomial, coefficient) in other.terms.iter() {
+            sum.add_term(monomial.clone(), *coefficient)?;
+        }
+
+        Some(sum)
+    }
+
+    /// Product of both polynomials, or `None` if a coefficient overflows.
+    pub fn checked_mul(&self, other: &Polynomial) -> Option<Polynomial> {
+        let mut product = Polynomial::default();
+
+        for (left, a) in self.terms.iter() {
+            for (right, b) in other.terms.iter() {
+                let mut monomial = [&left[..], &right[..]].concat();
+                monomial.sort_unstable();
+                product.add_term(monomial, a.checked_mul(*b)?)?;
+            }
+        }
+
+        Some(product)
+    }
+
+    /// Highest power of `variable` in any term.
+    pub fn degree_in(&self, variable: usize) -> usize {
+        self.terms.keys()
+            .map(|monomial| monomial.iter().filter(|v| **v == variable).count())
+            .max()
+            .unwrap_or(0)
+    }
+
+    /// Number of variables the polynomial may use, one more than the
+    /// highest variable in any term.
+    pub fn variables(&self) -> usize {
+        self.terms.keys().flat_map(|monomial| monomial.last()).max().map_or(0, |variable| variable + 1)
+    }
+
+    /// Value of the polynomial with `values[i]` substituted for variable `i`,
+    /// or `None` if it overflows or a variable has no value.
+    pub fn evaluate(&self, values: &[i64]) -> Option<i64> {
+        self.terms.iter().try_fold(0i64, |sum, (monomial, coefficient)| {
+            let term = monomial.iter().try_fold(*coefficient, |product, v| product.checked_mul(*values.get(*v)?))?;
+            sum.checked_add(term)
+        })
+    }
+
+    /// Split into the terms without `variable` and the coefficient of
+    /// `variable` in the remaining terms, which must be linear in it.
+    fn split_linear(&self, variable: usize) -> (Polynomial, Polynomial) {
+        let mut rest = Polynomial::default();
+        let mut coefficient = Polynomial::default();
+
+        for (monomial, value) in self.terms.iter() {
+            match monomial.iter().position(|v| *v == variable) {
+                Some(position) => {
+                    let mut monomial = monomial.clone();
+                    monomial.remove(position);
+                    coefficient.terms.insert(monomial, *value);
+                },
+                None => {
+                    rest.terms.insert(monomial.clone(), *value);
+                },
+            }
+        }
+
+        (rest, coefficient)
+    }
+
+    /// Render the polynomial using `names[i]` for variable `i`, highest
+    /// degree terms first.
+    pub fn format(&self, names: &[&str]) -> Result<String, SymbolicError> {
+        if self.variables() > names.len() {
+            return Err(SymbolicError::MissingVariable { variable: self.variables() - 1 });
+        }
+
+        let mut terms: Vec<(&Vec<usize>, &i64)> = self.terms.iter().collect();
+        terms.sort_by_key(|(monomial, _)| (Reverse(monomial.len()), (*monomial).clone()));
+
+        let mut text = String::new();
+        for (position, (monomial, coefficient)) in terms.into_iter().enumerate() {
+            let sign = match (position, *coefficient < 0) {
+                (0, true) => "-",
+                (0, false) => "",
+                (_, true) => " - ",
+                (_, false) => " + ",
+            };
+            let magnitude = coefficient.unsigned_abs();
+
+            let mut factors: Vec<String> = vec![];
+            if magnitude != 1 || monomial.is_empty() {
+                factors.push(magnitude.to_string());
+            }
+            let mut variables = monomial.iter().peekable();
+            while let Some(variable) = variables.next() {
+                let mut power = 1;
+                while variables.peek() == Some(&variable) {
+                    variables.next();
+                    power += 1;
+                }
+
+                match power {
+                    1 => factors.push(names[*variable].to_string()),
+                    _ => factors.push(format!("{}^{}", names[*variable], power)),
+                }
+            }
+
+            text.push_str(sign);
+            text.push_str(&factors.join("*"));
+        }
+
+        if text.is_empty() {
+            text.push('0');
+        }
+
+        Ok(text)
+    }
+}
+
+/// Memory after symbolic execution. `None` marks cells computed from a read
+/// through a symbolic address.
+#[derive(Debug, PartialEq, Clone)]
+pub struct SymbolicMemory {
+    cells: Vec<Option<Polynomial>>,
+}
+
+impl SymbolicMemory {
+    /// The polynomial stored at `address`. Cells beyond the end hold zero.
+    pub fn get(&self, address: usize) -> Result<Polynomial, SymbolicError> {
+        match self.cells.get(address) {
+            Some(Some(polynomial)) => Ok(polynomial.clone()),
+            Some(None) => Err(SymbolicError::Unknown { address }),
+            None => Ok(Polynomial::default()),
+        }
+    }
+
+    fn concrete(&self, address: usize) -> Result<i64, SymbolicError> {
+        match self.cells.get(address) {
+            Some(Some(polynomial)) => polynomial.as_constant().ok_or(SymbolicError::SymbolicControl { address }),
+            Some(None) => Err(SymbolicError::SymbolicControl { address }),
+            None => Ok(0),
+        }
+    }
+
+    fn write(&mut self, address: usize, value: Option<Polynomial>) -> Result<(), SymbolicError> {
+        if address >= self.cells.len() {
+            let len = address.checked_add(1).ok_or(IntcodeError::OutOfBoundsWrite { address })?;
+            self.cells.try_reserve(len - self.cells.len())
+                .map_err(|_| IntcodeError::OutOfBoundsWrite { address })?;
+            self.cells.resize(len, Some(Polynomial::default()));
+        }
+
+        self.cells[address] = value;
+        Ok(())
+    }
+}
+
+fn to_address(value: i64) -> Result<usize, SymbolicError> {
+    if value < 0 {
+        Err(IntcodeError::NegativeAddress { value }.into())
+    } else {
+        Ok(value as usize)
+    }
+}
+
+/// Run `memory` from address zero with variable `i` stored at `variables[i]`.
+pub fn execute(memory: &[i64], variables: &[usize]) -> Result<SymbolicMemory, SymbolicError> {
+    let mut memory = SymbolicMemory {
+        cells: memory.iter().map(|value| Some(Polynomial::constant(*value))).collect(),
+    };
+    for (variable, address) in variables.iter().enumerate() {
+        memory.write(*address, Some(Polynomial::variable(variable)))?;
+    }
+
+    let mut index = 0;
+    loop {
+        let word = memory.concrete(index)?;
+        let instruction = Instruction::parse(word).map_err(|error| IntcodeError::decode(index, word, error))?;
+
+        match instruction.opcode {
+            Opcode::Exit => return Ok(memory),
+            Opcode::Addition | Opcode::Multiplication => {},
+            opcode => return Err(SymbolicError::Unsupported { address: index, opcode }),
+        }
+
+        let parameters = instruction.parameters();
+        let mut operands = vec![];
+        for parameter in parameters[..2].iter() {
+            let address = index + 1 + parameter.position;
+
+            let word = memory.get(address).ok();
+
+            // Without `arb` the relative base stays zero, so relative mode
+            // reads like position mode.
+            operands.push(match (parameter.mode, word.as_ref().and_then(Polynomial::as_constant)) {
+                (ParameterMode::Immediate, _) => word,
+                (_, Some(pointer)) => memory.get(to_address(pointer)?).ok(),
+                (_, None) => None,
+            });
+        }
+
+        let destination = index + 1 + parameters[2].position;
+        if parameters[2].mode == ParameterMode::Immediate {
+            return Err(IntcodeError::InvalidParameterMode { address: index, mode: 1 }.into());
+        }
+        let destination = to_address(memory.concrete(destination)?)?;
+
+        let result = match (&operands[0], &operands[1]) {
+            (Some(left), Some(right)) => Some(match instruction.opcode {
+                Opcode::Addition => left.checked_add(right),
+                _ => left.checked_mul(right),
+            }
+            .ok_or(IntcodeError::ArithmeticOverflow { address: index })?),
+            _ => None,
+        };
+
+        memory.write(destination, result)?;
+        index += instruction.size();
+    }
+}
+
+/// Every assignment of values from `ranges` for which `polynomial` equals
+/// `target`, with the last variable varying fastest.
+///
+/// The last variable the polynomial is at most linear in is solved for
+/// directly; the others are enumerated.
+pub fn solve(
+    polynomial: &Polynomial,
+    target: i64,
+    ranges: &[RangeInclusive<i64>],
+) -> Result<Vec<Vec<i64>>, SymbolicError> {
+    if polynomial.variables() > ranges.len() {
+        return Err(SymbolicError::MissingVariable { variable: polynomial.variables() - 1 });
+    }
+
+    if ranges.is_empty() {
+        return Ok(match polynomial.evaluate(&[]) {
+            Some(value) if value == target => vec![vec![]],
+            _ => vec![],
+        });
+    }
+
+    let solved = (0..ranges.len())
+        .rev()
+        .find(|variable| polynomial.degree_in(*variable) <= 1)
+        .ok_or(SymbolicError::Nonlinear)?;
+    let (rest, coefficient) = polynomial.split_linear(solved);
+
+    let mut solutions = vec![];
+    let mut values: Vec<i64> = ranges.iter().map(|range| *range.start()).collect();
+    if ranges.iter().any(|range| range.is_empty()) {
+        return Ok(solutions);
+    }
+
+    loop {
+        if let (Some(rest), Some(coefficient)) = (rest.evaluate(&values), coefficient.evaluate(&values)) {
+            let remainder = target.checked_sub(rest);
+
+            match (remainder, coefficient) {
+                (Some(0), 0) => {
+                    for value in ranges[solved].clone() {
+                        values[solved] = value;
+                        solutions.push(values.clone());
+                    }
+                },
+                // `checked_rem` rules out a zero coefficient and the
+                // overflowing `i64::MIN / -1`, which has no solution in range.
+                (Some(remainder), coefficient) if remainder.checked_rem(coefficient) == Some(0) => {
+                    let value = remainder / coefficient;
+                    if ranges[solved].contains(&value) {
+                        values[solved] = value;
+                        solutions.push(values.clone());
+                    }
+                },
+                _ => {},
+            }
+        }
+
+        // Advance the enumerated variables like an odometer, skipping `solved`.
+        let mut variable = ranges.len();
+        loop {
+            if variable == 0 {
+                solutions.sort();
+                return Ok(solutions);
+            }
+            variable -= 1;
+
+            if variable == solved {
+                continue;
+            }
+            if values[variable] < *ranges[variable].end() {
+                values[variable] += 1;
+                break;
+            }
+            values[variable] = *ranges[variable].start();
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    /// A gravity assist program in the shape of the puzzle input.
+    const GRAVITY_ASSIST: [i64; 25] = [
+        1, 0, 0, 3,
+        1, 1, 2, 3,
+        1, 3, 4, 3,
+        2, 1, 24, 21,
+        1, 21, 2, 0,
+        99, 0, 0, 0, 7,
+    ];
+
+    #[test]
+    fn it_derives_a_polynomial_for_address_zero() {
+        let memory = execute(&GRAVITY_ASSIST, &[1, 2]).unwrap();
+        let polynomial = memory.get(0).unwrap();
+
+        assert_eq!(polynomial.format(&["noun", "verb"]).unwrap(), "7*noun + verb");
+        assert_eq!(memory.get(3).unwrap().format(&["noun", "verb"]).unwrap(), "noun + verb + 1");
+        assert_eq!(polynomial.evaluate(&[12, 2]), Some(86));
+    }
+
+    #[test]
+    fn it_formats_polynomials() {
+        let x = Polynomial::variable(0);
+        let y = Polynomial::variable(1);
+        let polynomial = x.checked_mul(&x).unwrap()
+            .checked_mul(&Polynomial::constant(-3)).unwrap()
+            .checked_add(&x.checked_mul(&y).unwrap()).unwrap()
+            .checked_add(&Polynomial::constant(-4)).unwrap();
+
+        assert_eq!(polynomial.format(&["x", "y"]).unwrap(), "-3*x^2 + x*y - 4");
+        assert_eq!(Polynomial::default().format(&[]).unwrap(), "0");
+        assert_eq!(polynomial.degree_in(0), 2);
+        assert_eq!(polynomial.degree_in(1), 1);
+    }
+
+    #[test]
+    fn it_solves_for_every_matching_input() {
+        let polynomial = execute(&GRAVITY_ASSIST, &[1, 2]).unwrap().get(0).unwrap();
+        let solutions = solve(&polynomial, 100, &[0..=99, 0..=99]).unwrap();
+
+        assert_eq!(solutions.len(), 14);
+        assert_eq!(solutions[0], vec![1, 93]);
+        assert_eq!(solutions[13], vec![14, 2]);
+        assert!(solutions.iter().all(|values| polynomial.evaluate(values) == Some(100)));
+    }
+
+    #[test]
+    fn it_solves_without_overflowing() {
+        let negated = Polynomial::variable(0).checked_mul(&Polynomial::constant(-1)).unwrap();
+
+        assert_eq!(solve(&negated, i64::MIN, &[0..=1]), Ok(vec![]));
+        assert_eq!(solve(&negated, -1, &[0..=1]), Ok(vec![vec![1]]));
+    }
+
+    #[test]
+    fn it_needs_every_variable_named_and_bounded() {
+        let polynomial = Polynomial::variable(0).checked_add(&Polynomial::variable(1)).unwrap();
+
+        assert_eq!(polynomial.variables(), 2);
+        assert_eq!(polynomial.evaluate(&[1]), None);
+        assert_eq!(polynomial.format(&["x"]), Err(SymbolicError::MissingVariable { variable: 1 }));
+        assert_eq!(solve(&polynomial, 3, &[0..=9]), Err(SymbolicError::MissingVariable { variable: 1 }));
+    }
+
+    #[test]
+    fn it_refuses_programs_it_cannot_follow() {
+        assert_eq!(execute(&[1, 0, 0, 0, 4, 0, 99], &[]), Err(SymbolicError::Unsupported {
+            address: 4,
+            opcode: Opcode::Output,
+        }));
+        assert_eq!(execute(&[1, 4, 4, 1, 99], &[4]), Err(SymbolicError::SymbolicControl { address: 4 }));
+        assert_eq!(execute(&[1, 1, 1, 5, 99, 0], &[1]).unwrap().get(5), Err(SymbolicError::Unknown { address: 5 }));
+    }
+
+    #[test]
+    fn it_refuses_to_grow_memory_without_bound() {
+        let address = i64::MAX as usize;
+
+        assert_eq!(execute(&[1101, 1, 1, i64::MAX, 99], &[]), Err(IntcodeError::OutOfBoundsWrite { address }.into()));
+        assert_eq!(execute(&[99], &[usize::MAX]), Err(IntcodeError::OutOfBoundsWrite {
+            address: usize::MAX,
+        }.into()));
+    }
+}
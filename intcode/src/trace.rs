@@ -0,0 +1,110 @@
+//! Execution traces written as JSON Lines, one object per executed
+//! instruction:
+//!
+//! ```text
+//! {"step":0,"address":0,"word":1002,"opcode":"Multiplication","parameters":[33,3,4],"writes":[{"address":4,"old":33,"new":99}],"input":null,"output":null}
+//! ```
+//!
+//! `parameters` holds the values after resolving parameter modes, except for
+//! the parameters an instruction writes to, which hold destination addresses.
+//! `writes` lists every cell the instruction changed, in order.
+
+use std::io::Write;
+
+use crate::error::IntcodeError;
+use crate::program::Execution;
+
+/// Receives every instruction executed by `Program::run_traced`.
+pub trait Tracer {
+    fn record(&mut self, execution: &Execution) -> Result<(), IntcodeError>;
+}
+
+/// Keeps every execution in memory.
+impl Tracer for Vec<Execution> {
+    fn record(&mut self, execution: &Execution) -> Result<(), IntcodeError> {
+        self.push(execution.clone());
+
+        Ok(())
+    }
+}
+
+fn optional(value: Option<i64>) -> String {
+    match value {
+        Some(value) => value.to_string(),
+        None => "null".to_string(),
+    }
+}
+
+/// Format a single execution as a JSON object.
+pub fn to_json(step: u64, execution: &Execution) -> String {
+    let parameters: Vec<String> = execution.parameters().iter()
+        .map(|value| value.to_string())
+        .collect();
+    let writes: Vec<String> = execution.writes().iter()
+        .map(|write| format!(r#"{{"address":{},"old":{},"new":{}}}"#, write.address, write.old, write.new))
+        .collect();
+
+    format!(
+        r#"{{"step":{},"address":{},"word":{},"opcode":"{:?}","parameters":[{}],"writes":[{}],"input":{},"output":{}}}"#,
+        step,
+        execution.address,
+        execution.word,
+        execution.opcode,
+        parameters.join(","),
+        writes.join(","),
+        optional(execution.input),
+        optional(execution.output),
+    )
+}
+
+/// Writes one JSON object per line to the underlying writer.
+pub struct JsonLinesTracer<W> {
+    writer: W,
+    step: u64,
+}
+
+impl<W: Write> JsonLinesTracer<W> {
+    pub fn new(writer: W) -> JsonLinesTracer<W> {
+        JsonLinesTracer { writer, step: 0 }
+    }
+
+    pub fn into_inner(self) -> W {
+        self.writer
+    }
+}
+
+impl<W: Write> Tracer for JsonLinesTracer<W> {
+    fn record(&mut self, execution: &Execution) -> Result<(), IntcodeError> {
+        writeln!(self.writer, "{}", to_json(self.step, execution))
+            .map_err(|error| IntcodeError::Device { message: error.to_string() })?;
+        self.step += 1;
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::program::Program;
+    use std::collections::VecDeque;
+
+    #[test]
+    fn it_writes_one_json_object_per_instruction() {
+        let mut program = Program::from(vec![3, 7, 1002, 7, 3, 7, 104, 0, 99]);
+        let mut output = vec![];
+        let mut trace = vec![];
+        {
+            let mut tracer = JsonLinesTracer::new(&mut trace);
+            program.run_traced(&mut VecDeque::from(vec![5]), &mut output, &mut tracer).unwrap();
+        }
+
+        assert_eq!(output, vec![15]);
+        assert_eq!(String::from_utf8(trace).unwrap(), [
+            r#"{"step":0,"address":0,"word":3,"opcode":"Input","parameters":[7],"writes":[{"address":7,"old":0,"new":5}],"input":5,"output":null}"#,
+            r#"{"step":1,"address":2,"word":1002,"opcode":"Multiplication","parameters":[5,3,7],"writes":[{"address":7,"old":5,"new":15}],"input":null,"output":null}"#,
+            r#"{"step":2,"address":6,"word":104,"opcode":"Output","parameters":[15],"writes":[],"input":null,"output":15}"#,
+            r#"{"step":3,"address":8,"word":99,"opcode":"Exit","parameters":[],"writes":[],"input":null,"output":null}"#,
+        ].iter().map(|line| format!("{}\n", line)).collect::<String>());
+    }
+}